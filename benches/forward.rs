@@ -0,0 +1,53 @@
+//! Per-timestep cost of the reservoir forward paths, for typical reservoir sizes.
+//!
+//! Run with `cargo bench --bench forward`. When running live, one step has to fit in the
+//! timestep (2 ms by default) with room to spare.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use ndarray::Array1;
+use robodrummer::{
+    model::RhythmModel,
+    reservoir::{RealtimeReservoir, Reservoir},
+};
+
+const SIZES: [usize; 4] = [100, 500, 1000, 2000];
+
+fn reservoir(size: usize) -> Reservoir {
+    let mut nw = Reservoir::new_builder()
+        .seed(0)
+        .from_size_input_outputs(size, 1, 1, 0.1)
+        .build();
+    nw.generate_sparse();
+    nw
+}
+
+fn forward(c: &mut Criterion) {
+    let mut group = c.benchmark_group("forward");
+    let input = Array1::from_elem(1, 0.5);
+
+    for size in SIZES {
+        let mut nw = reservoir(size);
+        group.bench_with_input(BenchmarkId::new("reservoir", size), &input, |b, input| {
+            b.iter(|| nw.forward(black_box(input)))
+        });
+
+        let mut realtime: RealtimeReservoir<f64> = RealtimeReservoir::new(&nw);
+        group.bench_with_input(
+            BenchmarkId::new("realtime_f64", size),
+            &input,
+            |b, input| b.iter(|| realtime.step(black_box(input))),
+        );
+
+        let mut realtime: RealtimeReservoir<f32> = RealtimeReservoir::new(&nw);
+        group.bench_with_input(
+            BenchmarkId::new("realtime_f32", size),
+            &input,
+            |b, input| b.iter(|| realtime.step(black_box(input))),
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, forward);
+criterion_main!(benches);
@@ -3,6 +3,7 @@
 */
 
 use clap::ValueEnum;
+use rand_distr::num_traits::Float;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone, ValueEnum, Default, Debug)]
@@ -23,6 +24,17 @@ impl Activation {
             Activation::Linear => input,
         }
     }
+
+    /// Apply the activation function in any floating point precision
+    pub fn apply_float<F: Float>(&self, input: F) -> F {
+        let (one, two) = (F::one(), F::one() + F::one());
+        match self {
+            Activation::ReLu => input.max(F::zero()),
+            Activation::Sigmoid => one / (one + (-input).exp()),
+            Activation::Tanh => two / (one + (-two * input).exp()) - one,
+            Activation::Linear => input,
+        }
+    }
 }
 
 pub fn relu(input: f64) -> f64 {
@@ -0,0 +1,108 @@
+/*!
+* Diagnostics of a trained reservoir, to understand why it does (or does not) keep time.
+*
+* The results are written to the output directory:
+* - `memory_capacity.csv`: the linear memory capacity per input delay
+* - `eigenvalues.csv`: the eigenvalue spectrum of the resonant weights
+* - `analysis.json`: a summary, including the Lyapunov exponent and readout weight statistics
+*/
+
+use std::{error::Error, fs};
+
+use ndarray::Array1;
+use ndarray_rand::RandomExt;
+use rand::{distributions::Uniform, rngs::StdRng, SeedableRng};
+use serde::Serialize;
+
+use crate::{
+    commands::AnalyzeArgs,
+    data::{get_model_metadata, load_train_data},
+    reservoir::{
+        analysis::{
+            eigenvalues, lyapunov_exponent, memory_capacity, readout_statistics, ReadoutStatistics,
+        },
+        Reservoir,
+    },
+};
+
+#[derive(Serialize)]
+struct Summary {
+    model: String,
+    spectral_radius: f64,
+    memory_capacity: f64,
+    lyapunov_exponent: f64,
+    /// whether the Lyapunov exponent was estimated with the training inputs
+    train_inputs: bool,
+    readout: Vec<ReadoutStatistics>,
+}
+
+pub fn analyze(args: AnalyzeArgs) -> Result<(), Box<dyn Error>> {
+    let nw = Reservoir::load_from_name(&args.model)?;
+    fs::create_dir_all(&args.output)?;
+
+    println!("Computing memory capacity");
+    let capacity = memory_capacity(&nw, args.max_delay, args.steps, args.seed)?;
+    let mut wtr = csv::Writer::from_path(args.output.join("memory_capacity.csv"))?;
+    wtr.write_record(["delay", "capacity"])?;
+    for (k, c) in capacity.iter().enumerate() {
+        wtr.write_record([(k + 1).to_string(), c.to_string()])?;
+    }
+    wtr.flush()?;
+
+    println!("Computing eigenvalues");
+    let eig = eigenvalues(&nw)?;
+    let mut wtr = csv::Writer::from_path(args.output.join("eigenvalues.csv"))?;
+    wtr.write_record(["re", "im", "norm"])?;
+    for e in &eig {
+        wtr.write_record([e.re.to_string(), e.im.to_string(), e.norm().to_string()])?;
+    }
+    wtr.flush()?;
+
+    // the Lyapunov exponent depends on the inputs, so use the ones the model was trained on
+    println!("Estimating the largest Lyapunov exponent");
+    let train_inputs = get_model_metadata(&args.model).and_then(|meta| {
+        let shift = meta
+            .shift
+            .map(|shift| (shift as f64 / meta.timestep).round() as usize);
+        load_train_data(
+            &meta.data,
+            meta.timestep,
+            meta.width,
+            meta.target_width,
+            shift,
+            meta.encoding,
+        )
+    });
+    let (inputs, train_inputs) = match train_inputs {
+        Ok((inputs, _)) => (inputs, true),
+        Err(e) => {
+            log::warn!("Can't load the training data ({}), using random inputs", e);
+            let mut rng = StdRng::seed_from_u64(args.seed);
+            let inputs = (0..args.steps)
+                .map(|_| Array1::random_using(nw.inputs, Uniform::new(0.0, 1.0), &mut rng))
+                .collect();
+            (inputs, false)
+        }
+    };
+    let lyapunov = lyapunov_exponent(&nw, &inputs)?;
+
+    let summary = Summary {
+        model: args.model,
+        spectral_radius: eig.first().map(|e| e.norm()).unwrap_or(0.0),
+        memory_capacity: capacity.iter().sum(),
+        lyapunov_exponent: lyapunov,
+        train_inputs,
+        readout: readout_statistics(&nw),
+    };
+    fs::write(
+        args.output.join("analysis.json"),
+        serde_json::to_string_pretty(&summary)?,
+    )?;
+
+    println!("Spectral radius:   {:.4}", summary.spectral_radius);
+    println!("Memory capacity:   {:.2}", summary.memory_capacity);
+    println!("Lyapunov exponent: {:.4}", summary.lyapunov_exponent);
+    println!("Results written to {}", args.output.display());
+
+    Ok(())
+}
@@ -0,0 +1,181 @@
+/*!
+* Data augmentation during training.
+*
+* Every iteration gets its own random rendering of the (time-domain) train data, in which the input
+* is made as sloppy as human input can be: the tempo changes, hits are early or late, some are
+* missing and some are extra, and the whole input may lag behind the targets. The targets keep
+* their timing relative to the (tempo scaled) pattern, so the model learns to play correctly
+* regardless.
+*/
+
+use std::collections::VecDeque;
+
+use rand::{distributions::Uniform, Rng};
+use rand_distr::Normal;
+
+use crate::{commands::TrainArgs, data::TrainData};
+
+/// The augmentations that are applied to the train data, all disabled when zero
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Augmentation {
+    /// maximum relative change of the tempo (e.g. 0.1 for ±10%)
+    pub tempo: f64,
+    /// standard deviation of the timing of the input hits [ms]
+    pub jitter: f64,
+    /// probability that an input hit is left out
+    pub dropout: f64,
+    /// probability of an extra hit after every input hit
+    pub extra_hits: f64,
+    /// maximum shift of the input relative to the targets [ms]
+    pub shift: f64,
+}
+
+impl Augmentation {
+    /// The augmentation of the train arguments, `None` if it is disabled
+    ///
+    /// # Errors
+    /// If a value is out of its range: the tempo must be in `[0, 1)`, the dropout and extra hit
+    /// probabilities in `[0, 1]`, and the jitter and shift can't be negative.
+    pub fn from_args(args: &TrainArgs) -> Result<Option<Self>, String> {
+        let augmentation = Augmentation {
+            tempo: args.augment_tempo,
+            jitter: args.augment_jitter,
+            dropout: args.augment_dropout,
+            extra_hits: args.augment_extra_hits,
+            shift: args.augment_shift,
+        };
+
+        if !(0.0..1.0).contains(&augmentation.tempo) {
+            return Err(format!(
+                "The tempo augmentation should be in [0, 1), got {}",
+                augmentation.tempo
+            ));
+        }
+        for (name, probability) in [
+            ("dropout", augmentation.dropout),
+            ("extra hits", augmentation.extra_hits),
+        ] {
+            if !(0.0..=1.0).contains(&probability) {
+                return Err(format!(
+                    "The {} augmentation is a probability in [0, 1], got {}",
+                    name, probability
+                ));
+            }
+        }
+        for (name, value) in [
+            ("jitter", augmentation.jitter),
+            ("shift", augmentation.shift),
+        ] {
+            if !(0.0..).contains(&value) {
+                return Err(format!(
+                    "The {} augmentation can't be negative, got {}",
+                    name, value
+                ));
+            }
+        }
+
+        Ok((augmentation != Augmentation::default()).then_some(augmentation))
+    }
+
+    /// A random rendering of the train data
+    pub fn apply<R: Rng>(&self, data: &TrainData, rng: &mut R) -> TrainData {
+        let tempo = 1.0 + rng.gen_range(-self.tempo..=self.tempo);
+        let shift = rng.gen_range(-self.shift..=self.shift);
+        let jitter = Normal::new(0.0, self.jitter).expect("the jitter should not be negative");
+
+        let mut inputs: Vec<(f64, f64)> = vec![];
+        for (i, &(time, velocity)) in data.inputs.iter().enumerate() {
+            if self.dropout > 0.0 && rng.gen_bool(self.dropout.min(1.0)) {
+                continue;
+            }
+            inputs.push((time * tempo + shift + rng.sample(jitter), velocity));
+
+            // a spurious hit somewhere before the next hit, with a random velocity
+            if self.extra_hits > 0.0 && rng.gen_bool(self.extra_hits.min(1.0)) {
+                if let Some((next, _)) = data.inputs.get(i + 1).filter(|(next, _)| *next > time) {
+                    let time = rng.gen_range(time..*next) * tempo + shift;
+                    inputs.push((time, rng.sample(Uniform::new_inclusive(0.0, 1.0))));
+                }
+            }
+        }
+
+        // hits before the start of the data can't be played
+        inputs.retain(|(time, _)| *time >= 0.0);
+        inputs.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        TrainData {
+            inputs: VecDeque::from(inputs),
+            targets: data
+                .targets
+                .iter()
+                .map(|(time, onsets)| (time * tempo, onsets.clone()))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn data() -> TrainData {
+        TrainData {
+            inputs: (0..100).map(|i| (i as f64 * 500.0, 1.0)).collect(),
+            targets: (0..200)
+                .map(|i| (i as f64 * 250.0, vec![i % 2 == 0]))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn augmentation_changes_the_inputs_only_as_configured() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let data = data();
+
+        let none = Augmentation::default().apply(&data, &mut rng);
+        assert_eq!(none.inputs, data.inputs);
+        assert_eq!(none.targets, data.targets);
+
+        let sloppy = Augmentation {
+            dropout: 0.3,
+            extra_hits: 0.05,
+            ..Default::default()
+        }
+        .apply(&data, &mut rng);
+        assert_ne!(sloppy.inputs.len(), data.inputs.len());
+        assert!(sloppy
+            .inputs
+            .iter()
+            .zip(sloppy.inputs.iter().skip(1))
+            .all(|(a, b)| a.0 <= b.0));
+        assert_eq!(sloppy.targets, data.targets);
+
+        let fast = Augmentation {
+            tempo: 0.5,
+            ..Default::default()
+        }
+        .apply(&data, &mut rng);
+        let tempo = fast.targets[1].0 / data.targets[1].0;
+        assert!((0.5..=1.5).contains(&tempo));
+        assert!((fast.inputs[1].0 - data.inputs[1].0 * tempo).abs() < 1e-9);
+    }
+
+    #[test]
+    fn invalid_augmentation_is_rejected() {
+        let args = |f: fn(&mut TrainArgs)| {
+            let mut args = TrainArgs::default();
+            f(&mut args);
+            Augmentation::from_args(&args)
+        };
+
+        assert_eq!(args(|_| {}), Ok(None));
+        assert!(args(|a| a.augment_dropout = 0.2).unwrap().is_some());
+        assert!(args(|a| a.augment_tempo = 1.0).is_err());
+        assert!(args(|a| a.augment_dropout = 1.5).is_err());
+        assert!(args(|a| a.augment_extra_hits = -0.1).is_err());
+        assert!(args(|a| a.augment_jitter = -1.0).is_err());
+        assert!(args(|a| a.augment_shift = f64::NAN).is_err());
+    }
+}
@@ -518,7 +518,7 @@ fn arpeggio_loop(
     let _handle = std::thread::spawn(move || loop {
         let msg = midi_sub.recv_bytes(0).unwrap();
         let msg: MidiNoteMessage = bincode::deserialize(&msg).unwrap();
-        let MidiNoteMessage::InputNotes(mut chord) = msg else {
+        let MidiNoteMessage::InputNotes(mut chord, _) = msg else {
             continue;
         };
         chord.reverse();
@@ -1,6 +1,7 @@
 use std::error::Error;
 
 use super::DevArgs;
+use crate::analyze::analyze;
 use crate::hyper::hyper;
 use crate::test_robot::robot;
 
@@ -8,5 +9,6 @@ pub fn dev(args: DevArgs) -> Result<(), Box<dyn Error>> {
     match args.command {
         super::DevCommand::Robot(r) => robot(r),
         super::DevCommand::Hyper(h) => hyper(h),
+        super::DevCommand::Analyze(a) => analyze(a),
     }
 }
@@ -3,16 +3,21 @@ use std::{
     error::Error,
     f64::consts::PI,
     fmt::Display,
-    io::Write,
+    io::{IsTerminal, Write},
     ops::{Index, IndexMut},
 };
 
 use ndarray_rand::rand_distr::StandardNormal;
-use rand::Rng;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
-use crate::data::{data_dir, TrainData};
+use crate::{
+    data::{data_dir, TrainData},
+    errors::NeuronError,
+    midifile::{midi_to_streams, Streams},
+    session::{read_session, session_to_streams},
+};
 
-use super::GenerateDataArgs;
+use super::{GenerateDataArgs, TempoCurve};
 
 /// A Rhythmic Pattern is just a collection of onsets and silent rests
 ///
@@ -259,28 +264,172 @@ fn euclidean(n: usize, k: usize) -> RhythmPattern {
     sequence.as_pattern(n)
 }
 
-fn generate_input_times(mspb: f64, var: f64, duration: f64) -> Vec<f64> {
-    let mut rng = rand::thread_rng();
+/// The tempo (bpm) of a linear or step tempo curve at `time` [ms]
+fn tempo_at(args: &GenerateDataArgs, time: f64) -> f64 {
+    let end_bpm = args.end_bpm.unwrap_or(args.bpm);
+    let progress = (time / (args.duration_s * 1000.0)).clamp(0.0, 1.0);
+
+    match args.tempo_curve {
+        TempoCurve::Linear => args.bpm + (end_bpm - args.bpm) * progress,
+        TempoCurve::Step => {
+            let steps = args.tempo_steps.max(1) as f64;
+            let step = (progress * (steps + 1.0)).floor().min(steps);
+            args.bpm + (end_bpm - args.bpm) * step / steps
+        }
+        TempoCurve::Constant | TempoCurve::RandomWalk => args.bpm,
+    }
+}
+
+/// The time [ms] and tempo (bpm) of every beat that ends within the duration, followed by the
+/// end of the last beat
+fn generate_beats<R: Rng>(args: &GenerateDataArgs, rng: &mut R) -> Vec<(f64, f64)> {
+    let duration = args.duration_s * 1000.0;
+
+    if args.tempo_curve == TempoCurve::Constant {
+        let mspb = 60000.0 / args.bpm;
+        let n = (duration / mspb) as usize;
+        return (0..=n).map(|i| (i as f64 * mspb, args.bpm)).collect();
+    }
+
+    let mut beats: Vec<(f64, f64)> = vec![];
+    let mut time = 0.0;
+    loop {
+        let bpm = match (args.tempo_curve, beats.last()) {
+            (TempoCurve::RandomWalk, Some((_, bpm))) => {
+                let change: f64 = rng.sample(StandardNormal);
+                (bpm + change * args.tempo_drift).clamp(args.bpm / 2.0, args.bpm * 2.0)
+            }
+            _ => tempo_at(args, time),
+        };
+        beats.push((time, bpm));
 
-    // amount of beats to generate input data for
-    let n = (duration * 1000.0 / mspb) as usize;
-    let mut times = Vec::with_capacity(n);
+        time += 60000.0 / bpm;
+        if time > duration {
+            break;
+        }
+    }
 
-    for i in 0..n {
+    beats
+}
+
+fn generate_input_times<R: Rng>(beats: &[f64], var: f64, rng: &mut R) -> Vec<f64> {
+    let mut times = Vec::with_capacity(beats.len());
+
+    for (i, beat) in beats.iter().enumerate() {
         let mut offset: f64 = rng.sample(StandardNormal);
         offset *= var;
         if i == 0 {
             offset = 0.0;
         }
-        let time = (i as f64 * mspb) + offset;
-        times.push(time);
+        times.push(beat + offset);
     }
 
     times
 }
 
-fn patterns_to_csv(
-    patterns: &[RhythmPattern],
+/// Merge the target time series of multiple channels into one time series.
+///
+/// At every instant where any channel has a target, all channels get a value: channels without a
+/// target at that instant are not at an onset, so they get `false`.
+fn merge_channels(channels: &[Vec<(f64, bool)>]) -> VecDeque<(f64, Vec<bool>)> {
+    // instants closer together than this are considered to be the same [ms]
+    const SAME_INSTANT: f64 = 1e-6;
+
+    let mut events: Vec<(f64, usize, bool)> = channels
+        .iter()
+        .enumerate()
+        .flat_map(|(c, series)| series.iter().map(move |&(time, flag)| (time, c, flag)))
+        .collect();
+    events.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut merged: VecDeque<(f64, Vec<bool>)> = VecDeque::new();
+    for (time, channel, flag) in events {
+        match merged.back_mut() {
+            Some((last, onsets)) if time - *last < SAME_INSTANT => onsets[channel] |= flag,
+            _ => {
+                let mut onsets = vec![false; channels.len()];
+                onsets[channel] = flag;
+                merged.push_back((time, onsets));
+            }
+        }
+    }
+
+    merged
+}
+
+/// Generate a velocity (0 to 1) for each input hit.
+///
+/// Every `scale`-th hit starts a new target pattern period, and is accented.
+fn generate_input_velocities<R: Rng>(
+    count: usize,
+    args: &GenerateDataArgs,
+    rng: &mut R,
+) -> Vec<f64> {
+    (0..count)
+        .map(|i| {
+            let mut velocity = args.velocity;
+            if i % (args.scale.max(1) as usize) == 0 {
+                velocity += args.accent;
+            }
+            let offset: f64 = rng.sample(StandardNormal);
+            (velocity + offset * args.velocity_variance).clamp(0.0, 1.0)
+        })
+        .collect()
+}
+
+/// The input hits and the target time series (one per pattern) of the patterns.
+///
+/// Every `scale` input beats are one period of the target patterns, so the patterns follow the
+/// tempo curve. The generated tempo is stored in the arguments.
+fn patterns_to_streams(patterns: &[RhythmPattern], args: &mut GenerateDataArgs) -> Streams {
+    // a seed is always set by `gendata`, and stored in the metadata
+    let mut rng = StdRng::seed_from_u64(args.seed.unwrap_or_default());
+    let beats = generate_beats(args, &mut rng);
+    let scale = args.scale.max(1) as usize;
+
+    // the last beat only marks the end of the data
+    let n_periods = (beats.len() - 1) / scale;
+
+    // one target time series per pattern (voice)
+    let channels: Vec<Vec<(f64, bool)>> = patterns
+        .iter()
+        .map(|pattern| {
+            (0..n_periods)
+                .flat_map(|i| {
+                    let start = beats[i * scale].0;
+                    let length = beats[(i + 1) * scale].0 - start;
+
+                    // create one period of the target pattern
+                    let interpolate = match args.density {
+                        Some(d) => uniform(d as f64 / length),
+                        None => uniform(pattern.len() as f64 / length),
+                    };
+                    let period: Vec<(f64, bool)> = pattern.to_time_period(interpolate, length);
+
+                    log::debug!("Period: {:?}", period);
+
+                    period
+                        .into_iter()
+                        .map(move |(time, flag)| (time + start, flag))
+                })
+                .collect()
+        })
+        .collect();
+
+    let beats = &beats[..beats.len() - 1];
+    let beat_times: Vec<f64> = beats.iter().map(|(time, _)| *time).collect();
+    let input_times = generate_input_times(&beat_times, args.variance, &mut rng);
+    let velocities = generate_input_velocities(input_times.len(), args, &mut rng);
+    args.tempo = beats.to_vec();
+
+    (input_times.into_iter().zip(velocities).collect(), channels)
+}
+
+/// Write the inputs and the targets (one time series per channel) as train data, with the
+/// arguments as metadata
+fn write_train_data(
+    mut inputs: VecDeque<(f64, f64)>,
+    channels: &[Vec<(f64, bool)>],
     args: &GenerateDataArgs,
 ) -> Result<(), Box<dyn Error>> {
     let data_path = data_dir()?;
@@ -307,8 +456,12 @@ fn patterns_to_csv(
         meta_path
     );
 
-    // checking if user wants to overwrite
-    if csv_path.exists() {
+    // checking if user wants to overwrite, scripts need to pass --force
+    if csv_path.exists() && !args.force {
+        if !std::io::stdin().is_terminal() {
+            return Err(NeuronError::AlreadyExists(format!("Data {}", name)).into());
+        }
+
         log::warn!("File already exists, checking with user.");
         println!("The file \x1b[1malready exists\x1b[0m, do you want to overwrite it? [y/N]");
         let mut input = String::new();
@@ -337,114 +490,56 @@ fn patterns_to_csv(
 
     // write the header
     let mut header = vec!["t".to_string(), "input".to_string()];
-    for i in 0..patterns.len() {
+    for i in 0..channels.len() {
         header.push(format!("target_{}", i));
     }
     csv_writer.write_record(header)?;
 
-    // calculate time between two pulses
-    // TODO: input beat scaling
-    let mspb = 60000.0 / args.bpm;
-    let mspb_target = mspb * args.scale as f64;
-
-    // create one period of the target pattern
-    let interpolates = match args.density {
-        Some(d) => {
-            let mut res = vec![];
-            for _ in patterns {
-                res.push(uniform(d as f64 / mspb_target));
-            }
-            res
-        }
-        None => {
-            let mut res = vec![];
-            for p in patterns {
-                res.push(uniform(p.len() as f64 / mspb_target));
-            }
-            res
-        }
-    };
-
-    // TODO: continue support for multiple outputs (thus multiple targets (thus multiple patterns))
-    let pattern = patterns.first().unwrap();
-    let interpolate = interpolates.first().unwrap();
-
-    let period: Vec<(f64, bool)> = pattern.to_time_period(interpolate, mspb_target);
-
-    log::debug!("Period: {:?}", period);
-
-    let n_periods = (args.duration_s * 1000.0 / mspb_target) as usize;
-    let mut targets: VecDeque<(f64, bool)> = (0..n_periods)
-        .flat_map(|i| {
-            period
-                .iter()
-                .map(move |&(time, flag)| (time + mspb_target * (i as f64), flag))
-        })
-        .collect();
-    let mut inputs: VecDeque<(f64, bool)> =
-        generate_input_times(mspb, args.variance, args.duration_s)
-            .iter()
-            .map(|x| (*x, true))
-            .collect();
+    let mut targets = merge_channels(channels);
 
     // create the data object
     let train_data = TrainData {
         inputs: inputs.clone(),
         targets: targets.clone(),
     };
-    let train_data = bincode::serialize(&train_data)?;
-    bin_file.write_all(&train_data)?;
+    bin_file.write_all(&train_data.to_bytes()?)?;
+
+    let flag = |b: bool| match b {
+        true => "1".to_string(),
+        false => "0".to_string(),
+    };
+    let no_targets = vec!["".to_string(); channels.len()];
 
     while !targets.is_empty() && !inputs.is_empty() {
         // write either an input, a target or both
+        let mut record = vec![];
         match targets[0].0.total_cmp(&inputs[0].0) {
             // next data is target
             std::cmp::Ordering::Less => {
-                csv_writer.write_record([
-                    targets[0].0.to_string(),
-                    "".to_string(),
-                    match targets[0].1 {
-                        true => "1".to_string(),
-                        false => "0".to_string(),
-                    },
-                ])?;
-                targets.pop_front().unwrap();
+                let (time, onsets) = targets.pop_front().unwrap();
+                record.push(time.to_string());
+                record.push("".to_string());
+                record.extend(onsets.into_iter().map(flag));
             }
             // next data is input
             std::cmp::Ordering::Greater => {
-                csv_writer.write_record([
-                    inputs[0].0.to_string(),
-                    match inputs[0].1 {
-                        true => "1".to_string(),
-                        false => "0".to_string(),
-                    },
-                    "".to_string(),
-                ])?;
-                inputs.pop_front().unwrap();
+                let (time, velocity) = inputs.pop_front().unwrap();
+                record.push(time.to_string());
+                record.push(velocity.to_string());
+                record.extend(no_targets.iter().cloned());
             }
             // next data is both
             std::cmp::Ordering::Equal => {
-                csv_writer.write_record([
-                    inputs[0].0.to_string(),
-                    match inputs[0].1 {
-                        true => "1",
-                        false => "0",
-                    }
-                    .to_string(),
-                    match targets[0].1 {
-                        true => "1",
-                        false => "0",
-                    }
-                    .to_string(),
-                ])?;
-                targets.pop_front().unwrap();
-                inputs.pop_front().unwrap();
+                let (time, velocity) = inputs.pop_front().unwrap();
+                let (_, onsets) = targets.pop_front().unwrap();
+                record.push(time.to_string());
+                record.push(velocity.to_string());
+                record.extend(onsets.into_iter().map(flag));
             }
         }
+        csv_writer.write_record(record)?;
     }
 
-    // TODO:
-
     Ok(())
 }
 
@@ -454,7 +549,7 @@ fn patterns_to_csv(
 ///
 /// # Result
 /// This function writes to a csv file
-pub fn gendata(args: GenerateDataArgs) -> Result<(), Box<dyn Error>> {
+pub fn gendata(mut args: GenerateDataArgs) -> Result<(), Box<dyn Error>> {
     // pseudo code
 
     // arguments:
@@ -463,6 +558,11 @@ pub fn gendata(args: GenerateDataArgs) -> Result<(), Box<dyn Error>> {
     //      - NP-DAG
     // - parameters for the sub-algorithm...
 
+    // a tempo of zero would never reach the end of the data
+    if args.bpm <= 0.0 || args.end_bpm.is_some_and(|bpm| bpm <= 0.0) {
+        return Err("The tempo (bpm) should be positive".into());
+    }
+
     let target_patterns = match &args.algorithm {
         super::RhythmAlgorithm::Euclidean(e) => {
             assert!(
@@ -475,6 +575,31 @@ pub fn gendata(args: GenerateDataArgs) -> Result<(), Box<dyn Error>> {
             }
             res
         }
+        super::RhythmAlgorithm::FromMidi(m) => {
+            let bytes = std::fs::read(&m.file)
+                .map_err(|e| format!("Could not read {}: {}", m.file.display(), e))?;
+            let (inputs, channels) = midi_to_streams(&bytes, m, args.bpm)?;
+            log::info!(
+                "Read {} inputs and {} target channels from {}",
+                inputs.len(),
+                channels.len(),
+                m.file.display()
+            );
+
+            return write_train_data(inputs, &channels, &args);
+        }
+        super::RhythmAlgorithm::FromSession(s) => {
+            let events = read_session(&s.session)?;
+            let (inputs, channels) = session_to_streams(&events, s, args.bpm)?;
+            log::info!(
+                "Read {} inputs from the {} events of session {}",
+                inputs.len(),
+                events.len(),
+                s.session
+            );
+
+            return write_train_data(inputs, &channels, &args);
+        }
         _ => todo!("Other algorithms are not yet implemented."),
     };
 
@@ -482,5 +607,87 @@ pub fn gendata(args: GenerateDataArgs) -> Result<(), Box<dyn Error>> {
         tp.show();
     }
 
-    patterns_to_csv(&target_patterns, &args)
+    // make sure that this dataset can be reproduced from its metadata
+    let seed = *args.seed.get_or_insert_with(rand::random);
+    log::info!("Using seed {}", seed);
+
+    let (inputs, channels) = patterns_to_streams(&target_patterns, &mut args);
+    write_train_data(inputs, &channels, &args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(tempo_curve: TempoCurve, end_bpm: Option<f64>) -> GenerateDataArgs {
+        GenerateDataArgs {
+            bpm: 60.0,
+            end_bpm,
+            duration_s: 10.0,
+            tempo_curve,
+            tempo_steps: 1,
+            tempo_drift: 2.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn linear_tempo_reaches_the_end_tempo() {
+        let args = args(TempoCurve::Linear, Some(120.0));
+
+        assert_eq!(tempo_at(&args, 0.0), 60.0);
+        assert_eq!(tempo_at(&args, 5000.0), 90.0);
+        assert_eq!(tempo_at(&args, 10_000.0), 120.0);
+        // the tempo stays at the end tempo after the duration
+        assert_eq!(tempo_at(&args, 20_000.0), 120.0);
+    }
+
+    #[test]
+    fn step_tempo_switches_halfway() {
+        let args = args(TempoCurve::Step, Some(120.0));
+
+        assert_eq!(tempo_at(&args, 4999.0), 60.0);
+        assert_eq!(tempo_at(&args, 5000.0), 120.0);
+
+        let beats = generate_beats(&args, &mut StdRng::seed_from_u64(0));
+        assert_eq!(beats[4], (4000.0, 60.0));
+        assert_eq!(beats[5], (5000.0, 120.0));
+        assert_eq!(beats[6], (5500.0, 120.0));
+    }
+
+    #[test]
+    fn random_walk_stays_within_bounds() {
+        let mut args = args(TempoCurve::RandomWalk, None);
+        args.duration_s = 600.0;
+        args.tempo_drift = 20.0;
+
+        let beats = generate_beats(&args, &mut StdRng::seed_from_u64(0));
+
+        assert!(beats.iter().all(|(_, bpm)| (30.0..=120.0).contains(bpm)));
+        assert!(beats.iter().any(|(_, bpm)| *bpm != 60.0));
+    }
+
+    #[test]
+    fn constant_tempo_matches_the_fixed_grid() {
+        let args = args(TempoCurve::Constant, None);
+        let (mspb, var) = (1000.0, 20.0);
+
+        let mut rng = StdRng::seed_from_u64(5);
+        let beats = generate_beats(&args, &mut rng);
+        let beat_times: Vec<f64> = beats[..beats.len() - 1].iter().map(|b| b.0).collect();
+        let times = generate_input_times(&beat_times, var, &mut rng);
+
+        // the generator before tempo curves: n beats at a fixed interval
+        let mut rng = StdRng::seed_from_u64(5);
+        let n = (args.duration_s * 1000.0 / mspb) as usize;
+        let fixed: Vec<f64> = (0..n)
+            .map(|i| {
+                let offset: f64 = rng.sample(StandardNormal);
+                i as f64 * mspb + if i == 0 { 0.0 } else { offset * var }
+            })
+            .collect();
+
+        assert_eq!(beats.len(), n + 1);
+        assert_eq!(times, fixed);
+    }
 }
@@ -64,7 +64,8 @@ impl<const L: usize> MidiFilter<L> {
         }
     }
 
-    fn chord_played(&mut self) -> Option<Vec<MidiNote>> {
+    /// Returns the notes of the chord and their highest velocity, if a chord was played
+    fn chord_played(&mut self) -> Option<(Vec<MidiNote>, u8)> {
         let last_time = self.stamps[self.pointer];
         let first_time = self.stamps[self.idx_n_back(self.chord_size - 1)];
 
@@ -74,10 +75,13 @@ impl<const L: usize> MidiFilter<L> {
         } else {
             self.last_chord_time = last_time;
             let mut chord = Vec::new();
+            let mut velocity = 0;
             for i in 0..self.chord_size {
-                chord.push(self.notes[self.idx_n_back(i)].key);
+                let note = &self.notes[self.idx_n_back(i)];
+                chord.push(note.key);
+                velocity = velocity.max(note.value);
             }
-            Some(chord)
+            Some((chord, velocity))
         }
     }
 }
@@ -96,8 +100,8 @@ fn chord(
         let received = rx.recv_timeout(Duration::from_millis(100));
         if let Ok((timestamp, _, keyevent)) = received {
             midi_filter.add(timestamp, keyevent);
-            if let Some(chord) = midi_filter.chord_played() {
-                let msg = MidiNoteMessage::InputNotes(chord.clone());
+            if let Some((chord, velocity)) = midi_filter.chord_played() {
+                let msg = MidiNoteMessage::InputNotes(chord.clone(), velocity);
                 publisher.send(msg.to_bytes()?, 0)?;
                 // update the TUI
                 if let Some(sender) = &tui_sender {
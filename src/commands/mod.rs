@@ -8,6 +8,8 @@ mod dev;
 mod gendata;
 mod metronome;
 mod midi_broker;
+mod models;
+mod record;
 mod run;
 mod train;
 
@@ -25,16 +27,25 @@ pub use dev::dev;
 pub use gendata::gendata;
 pub use metronome::metronome;
 pub use midi_broker::broke;
+pub use models::models;
+pub use record::record;
 pub use run::run;
 pub use train::train;
 
-use crate::activation::Activation;
+use crate::{
+    activation::Activation,
+    encoding::InputEncoding,
+    evaluation::{OnsetDetection, OnsetMetrics},
+    reservoir::readout::{lambda_grid, CrossValidation},
+};
 
 pub const METRONOME_PORT: u16 = 5432;
 pub const FEEL_PORT: u16 = 4321;
 pub const MIDI_PORT: u16 = 6543;
 pub const OUTPUT_PORT: u16 = 7654;
 pub const OSC_PORT: u16 = 30000;
+pub const STATE_PORT: u16 = 8765;
+pub const CONTROL_PORT: u16 = 9876;
 
 #[derive(Parser, Debug)]
 pub struct Arguments {
@@ -43,6 +54,8 @@ pub struct Arguments {
     pub command: Command,
 }
 
+// the arguments are only parsed once, so the size of the train arguments is not an issue
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, Debug)]
 pub enum Command {
     Train(TrainArgs),
@@ -54,6 +67,8 @@ pub enum Command {
     Combine(CombinerArgs),
     Tui(TuiArgs),
     Dev(DevArgs),
+    Models(ModelsArgs),
+    Record(RecordArgs),
     // Robot(RobotArgs),
 }
 
@@ -86,6 +101,46 @@ pub struct RunArgs {
     /// Port on which the network output is published using osc
     #[arg(short, long, default_value_t = OSC_PORT)]
     pub osc_port: u16,
+
+    /// Keep adapting the readout (recursive least squares) to the published output notes
+    #[arg(long, default_value_t = false)]
+    pub adapt: bool,
+
+    /// Port on which the output notes (teacher signal for adapting) come in
+    #[arg(long, default_value_t = OUTPUT_PORT)]
+    pub output_port: u16,
+
+    /// Forgetting factor of the live adaptation
+    #[arg(long, default_value_t = 0.999)]
+    pub forgetting: f64,
+
+    /// Run the model in single precision (f32), not possible while adapting
+    #[arg(long, default_value_t = false)]
+    pub f32: bool,
+
+    /// Publish the (decimated) model state every this many timesteps, 0 to disable
+    #[arg(long, default_value_t = 0)]
+    pub state_every: usize,
+
+    /// The maximum amount of state values per published state
+    #[arg(long, default_value_t = 64)]
+    pub state_size: usize,
+
+    /// Port on which the model state is published using zmq
+    #[arg(long, default_value_t = STATE_PORT)]
+    pub state_port: u16,
+
+    /// Listen for snapshot and recall requests on the control port
+    #[arg(long, default_value_t = false)]
+    pub control: bool,
+
+    /// Port on which to listen for snapshot and recall requests
+    #[arg(long, default_value_t = CONTROL_PORT)]
+    pub control_port: u16,
+
+    /// Start from a saved snapshot of the model
+    #[arg(long)]
+    pub restore: Option<String>,
 }
 
 impl Default for RunArgs {
@@ -98,11 +153,21 @@ impl Default for RunArgs {
             metronome_port: METRONOME_PORT,
             midi_port: MIDI_PORT,
             osc_port: OSC_PORT,
+            adapt: false,
+            output_port: OUTPUT_PORT,
+            forgetting: 0.999,
+            f32: false,
+            state_every: 0,
+            state_size: 64,
+            state_port: STATE_PORT,
+            control: false,
+            control_port: CONTROL_PORT,
+            restore: None,
         }
     }
 }
 
-#[derive(Args, Debug, Serialize, Deserialize, Default)]
+#[derive(Args, Debug, Clone, Serialize, Deserialize, Default)]
 pub struct TrainArgs {
     /// The size of the reservoir
     #[arg(short = 'n', long, default_value_t = 100)]
@@ -136,7 +201,7 @@ pub struct TrainArgs {
     #[arg(short, long, default_value_t = 1)]
     pub inputs: usize,
 
-    /// Amount of reservoir outputs
+    /// Amount of reservoir outputs (follows the amount of target channels in the data)
     #[arg(short, long, default_value_t = 1)]
     pub outputs: usize,
 
@@ -160,10 +225,60 @@ pub struct TrainArgs {
     #[arg(short, long, default_value = "default")]
     pub data: String,
 
+    /// Additional train data names, each used as an independent sequence (comma separated)
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub extra_data: Vec<String>,
+
+    /// Amount of timesteps at the start of every sequence of which the states are discarded
+    #[arg(long, default_value_t = 50)]
+    #[serde(default = "default_washout")]
+    pub washout: usize,
+
     /// List the available data names
     #[arg(long, default_value_t = false)]
     pub list_data: bool,
 
+    /// Augmentation: maximum relative tempo change of every iteration (e.g. 0.1 for ±10%)
+    #[arg(long, default_value_t = 0.0)]
+    #[serde(default)]
+    pub augment_tempo: f64,
+
+    /// Augmentation: standard deviation of the timing of the input hits [ms]
+    #[arg(long, default_value_t = 0.0)]
+    #[serde(default)]
+    pub augment_jitter: f64,
+
+    /// Augmentation: probability that an input hit is left out
+    #[arg(long, default_value_t = 0.0)]
+    #[serde(default)]
+    pub augment_dropout: f64,
+
+    /// Augmentation: probability of a spurious extra hit after every input hit
+    #[arg(long, default_value_t = 0.0)]
+    #[serde(default)]
+    pub augment_extra_hits: f64,
+
+    /// Augmentation: maximum shift of all input hits relative to the targets [ms]
+    #[arg(long, default_value_t = 0.0)]
+    #[serde(default)]
+    pub augment_shift: f64,
+
+    /// Save the trained model with this name, instead of asking for one
+    #[arg(long)]
+    #[serde(skip)]
+    pub save_as: Option<String>,
+
+    /// Overwrite an existing model with the same name
+    #[arg(long, default_value_t = false)]
+    #[serde(skip)]
+    pub force: bool,
+
+    /// Write a JSON report of the training (errors, metrics and paths) to this file
+    #[arg(long)]
+    #[serde(skip)]
+    pub report: Option<PathBuf>,
+
     /// Split between train and test
     #[arg(long, default_value_t = 0.8)]
     pub split: f64,
@@ -187,6 +302,163 @@ pub struct TrainArgs {
     /// The activation function to use
     #[arg(long = "act", default_value = "tanh", value_enum)]
     pub activation: Activation,
+
+    /// How input hits are encoded into the reservoir input
+    #[arg(long, default_value = "pulse", value_enum)]
+    #[serde(default)]
+    pub encoding: InputEncoding,
+
+    /// The structure of the reservoir weights
+    #[arg(long, default_value = "random", value_enum)]
+    #[serde(default)]
+    pub topology: Topology,
+
+    /// Distance between jump connections (cycle-jumps topology)
+    #[arg(long, default_value_t = 4)]
+    #[serde(default)]
+    pub jump: usize,
+
+    /// Amount of nearest neighbors in the ring lattice (small-world topology)
+    #[arg(long, default_value_t = 4)]
+    #[serde(default)]
+    pub neighbors: usize,
+
+    /// Probability (0 to 1) of rewiring a lattice connection (small-world topology)
+    #[arg(long, default_value_t = 0.1)]
+    #[serde(default)]
+    pub rewire: f64,
+
+    /// Feed the output back into the reservoir (teacher forced during training, single ESN only)
+    #[arg(long, default_value_t = false)]
+    #[serde(default)]
+    pub feedback: bool,
+
+    /// Scaling of the input weights
+    #[arg(long, default_value_t = 1.0)]
+    #[serde(default = "unit_scaling")]
+    pub input_scaling: f64,
+
+    /// Scaling of the reservoir bias, which is drawn uniformly from [-1, 1] (0: no bias)
+    #[arg(long, default_value_t = 0.0)]
+    #[serde(default)]
+    pub bias_scaling: f64,
+
+    /// Scaling of the output feedback weights
+    #[arg(long, default_value_t = 1.0)]
+    #[serde(default = "unit_scaling")]
+    pub feedback_scaling: f64,
+
+    /// The kind of model to train
+    #[arg(long, default_value = "esn", value_enum)]
+    #[serde(default)]
+    pub model: ModelKind,
+
+    /// Amount of time-delayed copies of the input (NVAR)
+    #[arg(long, default_value_t = 4)]
+    #[serde(default)]
+    pub delays: usize,
+
+    /// Amount of timesteps between the delayed copies of the input (NVAR)
+    #[arg(long, default_value_t = 10)]
+    #[serde(default)]
+    pub stride: usize,
+
+    /// Highest degree of the polynomial features (NVAR)
+    #[arg(long, default_value_t = 2)]
+    #[serde(default)]
+    pub order: usize,
+
+    /// Amount of stacked reservoirs (deep ESN), the readout sees the states of all layers
+    #[arg(long, default_value_t = 1)]
+    #[serde(default = "single_layer")]
+    pub layers: usize,
+
+    /// Leak rate of each layer of a deep ESN (missing layers use the neuron leak rate)
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub layer_leak_rates: Vec<f64>,
+
+    /// Spectral radius of each layer of a deep ESN (missing layers use the spectral radius)
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub layer_spectral_radii: Vec<f64>,
+
+    /// Seed for the random reservoir weights (random if not given, always stored in the metadata)
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Forgetting factor of recursive least squares training (1.0 is no forgetting)
+    #[arg(long, default_value_t = 1.0)]
+    #[serde(default = "no_forgetting")]
+    pub forgetting: f64,
+
+    /// Select the regularization parameter using k-fold cross-validation (amount of folds).
+    /// The readout is then trained with ridge regression, regardless of the mode.
+    #[arg(long = "cv")]
+    pub folds: Option<usize>,
+
+    /// Regularization values to try during cross-validation (default: 1e-8 to 1e2, log-spaced)
+    #[arg(long = "cv-lambdas", value_delimiter = ',')]
+    #[serde(default)]
+    pub cv_lambdas: Vec<f64>,
+
+    /// Result of the cross-validation, stored in the model metadata
+    #[arg(skip)]
+    pub cross_validation: Option<CrossValidation>,
+
+    /// How onsets are detected in the output, for the evaluation on the test split
+    #[arg(long, default_value = "threshold", value_enum)]
+    #[serde(default)]
+    pub onset_detection: OnsetDetection,
+
+    /// The output threshold of an onset
+    #[arg(long, default_value_t = 0.5)]
+    #[serde(default = "onset_threshold")]
+    pub onset_threshold: f64,
+
+    /// The maximum offset (ms) of a detected onset to match a target onset
+    #[arg(long, default_value_t = 20.0)]
+    #[serde(default = "onset_tolerance")]
+    pub onset_tolerance: f64,
+
+    /// Onset metrics on the test split, stored in the model metadata
+    #[arg(skip)]
+    pub evaluation: Option<OnsetMetrics>,
+}
+
+fn no_forgetting() -> f64 {
+    1.0
+}
+
+fn default_washout() -> usize {
+    50
+}
+
+fn single_layer() -> usize {
+    1
+}
+
+fn unit_scaling() -> f64 {
+    1.0
+}
+
+pub(crate) fn onset_threshold() -> f64 {
+    0.5
+}
+
+pub(crate) fn onset_tolerance() -> f64 {
+    20.0
+}
+
+impl TrainArgs {
+    /// The regularization values to try during cross-validation
+    pub fn cv_lambdas(&self) -> Vec<f64> {
+        if self.cv_lambdas.is_empty() {
+            lambda_grid(1e-8, 1e2, 11)
+        } else {
+            self.cv_lambdas.clone()
+        }
+    }
 }
 
 #[derive(ValueEnum, Debug, Clone, Serialize, Deserialize, Default)]
@@ -194,6 +466,8 @@ pub enum TrainMode {
     #[default]
     Inv,
     Grad,
+    Ridge,
+    Rls,
 }
 
 impl Display for TrainMode {
@@ -201,6 +475,53 @@ impl Display for TrainMode {
         match self {
             TrainMode::Inv => write!(f, "pseudo inverse"),
             TrainMode::Grad => write!(f, "MSE gradient descent"),
+            TrainMode::Ridge => write!(f, "ridge regression"),
+            TrainMode::Rls => write!(f, "recursive least squares"),
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
+pub enum ModelKind {
+    /// Echo state network (deep if more than one layer is used)
+    #[default]
+    Esn,
+    /// Nonlinear vector autoregression (next generation reservoir computing)
+    Nvar,
+}
+
+impl Display for ModelKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModelKind::Esn => write!(f, "ESN"),
+            ModelKind::Nvar => write!(f, "NVAR"),
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
+pub enum Topology {
+    /// Random sparse weights, with the given connectivity
+    #[default]
+    Random,
+    /// Simple cycle reservoir
+    Cycle,
+    /// Cycle reservoir with regular jumps
+    CycleJumps,
+    /// Delay line reservoir
+    DelayLine,
+    /// Small-world (Watts–Strogatz) graph
+    SmallWorld,
+}
+
+impl Display for Topology {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Topology::Random => write!(f, "Random ESN"),
+            Topology::Cycle => write!(f, "Simple cycle"),
+            Topology::CycleJumps => write!(f, "Cycle with jumps"),
+            Topology::DelayLine => write!(f, "Delay line"),
+            Topology::SmallWorld => write!(f, "Small world"),
         }
     }
 }
@@ -217,6 +538,10 @@ nest! {
                 Euclidean(EucledeanArgs),
                 NPDAG(NPDAGArgs),
                 PolyEuclidean(PolyEuclideanArgs),
+                /// Read the inputs and targets from a MIDI file (separate channels or notes)
+                FromMidi(FromMidiArgs),
+                /// Read the inputs and targets from a recorded session
+                FromSession(FromSessionArgs),
             },
 
         /// Chebyshev density (amount of zeros per beat, approximately)
@@ -231,6 +556,31 @@ nest! {
         #[arg(short, long, default_value_t = 120.0)]
         pub bpm: f64,
 
+        /// How the tempo changes over time, starting at `bpm`
+        #[arg(long, default_value = "constant", value_enum)]
+        #[serde(default)]
+        pub tempo_curve: TempoCurve,
+
+        /// The tempo at the end of a linear or step tempo curve (default: the start tempo)
+        #[arg(long)]
+        #[serde(default)]
+        pub end_bpm: Option<f64>,
+
+        /// The amount of tempo changes of a step tempo curve
+        #[arg(long, default_value_t = 1)]
+        #[serde(default = "single_step")]
+        pub tempo_steps: usize,
+
+        /// Standard deviation of the tempo change per beat of a random-walk tempo curve [bpm]
+        #[arg(long, default_value_t = 2.0)]
+        #[serde(default = "default_tempo_drift")]
+        pub tempo_drift: f64,
+
+        /// The time (ms) and tempo (bpm) of every generated input beat, stored in the metadata
+        #[arg(skip)]
+        #[serde(default)]
+        pub tempo: Vec<(f64, f64)>,
+
         /// The variance to apply to the input data (is actually std dev)
         #[arg(short, long, default_value_t = 5.0)]
         pub variance: f64,
@@ -246,6 +596,68 @@ nest! {
         /// Should the data generate a steady-state input phase (timesteps)
         #[arg(long, default_value_t = 0)]
         pub steady_state: usize,
+
+        /// The mean velocity (0 to 1) of the input hits
+        #[arg(long, default_value_t = 0.8)]
+        #[serde(default = "full_velocity")]
+        pub velocity: f64,
+
+        /// The standard deviation of the input hit velocities
+        #[arg(long = "velocity-variance", default_value_t = 0.1)]
+        #[serde(default)]
+        pub velocity_variance: f64,
+
+        /// Velocity added to the input hits that start a target pattern period
+        #[arg(long, default_value_t = 0.0)]
+        #[serde(default)]
+        pub accent: f64,
+
+        /// Seed for the random input timing and velocities (random if not given, always stored in the metadata)
+        #[arg(long)]
+        pub seed: Option<u64>,
+
+        /// Overwrite existing data with the same name without asking
+        #[arg(long, default_value_t = false)]
+        #[serde(skip)]
+        pub force: bool,
+    }
+}
+
+/// Data generated before velocities were supported only contains full-velocity hits
+fn full_velocity() -> f64 {
+    1.0
+}
+
+fn single_step() -> usize {
+    1
+}
+
+fn default_tempo_drift() -> f64 {
+    2.0
+}
+
+/// How the tempo of generated data changes over time
+#[derive(ValueEnum, Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
+pub enum TempoCurve {
+    /// The same tempo all the time
+    #[default]
+    Constant,
+    /// Accelerando or ritardando from `bpm` to `end-bpm`
+    Linear,
+    /// Sudden tempo changes from `bpm` to `end-bpm`, evenly spread over the duration
+    Step,
+    /// Random changes of the tempo every beat, within half and double `bpm`
+    RandomWalk,
+}
+
+impl Display for TempoCurve {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TempoCurve::Constant => write!(f, "constant"),
+            TempoCurve::Linear => write!(f, "linear"),
+            TempoCurve::Step => write!(f, "step"),
+            TempoCurve::RandomWalk => write!(f, "random walk"),
+        }
     }
 }
 
@@ -261,6 +673,8 @@ impl Display for RhythmAlgorithm {
             RhythmAlgorithm::Euclidean(_) => write!(f, "Euclidean"),
             RhythmAlgorithm::NPDAG(_) => write!(f, "NP-DAG"),
             RhythmAlgorithm::PolyEuclidean(_) => write!(f, "Poly Euclidean"),
+            RhythmAlgorithm::FromMidi(m) => write!(f, "MIDI file {}", m.file.display()),
+            RhythmAlgorithm::FromSession(s) => write!(f, "Session {}", s.session),
         }
     }
 }
@@ -311,6 +725,81 @@ pub struct PolyEuclideanArgs {
     pub scale: u8,
 }
 
+#[derive(Args, Debug, Serialize, Deserialize, Default)]
+pub struct FromMidiArgs {
+    /// The MIDI file (.mid) to read
+    pub file: PathBuf,
+
+    /// The MIDI channel (1 to 16) of the input notes (all channels if not given)
+    #[arg(long)]
+    pub input_channel: Option<u8>,
+
+    /// The input note numbers (all notes if not given)
+    #[arg(long, value_delimiter = ',')]
+    pub input_notes: Vec<u8>,
+
+    /// The MIDI channel (1 to 16) of the target notes (all channels if not given)
+    #[arg(long)]
+    pub target_channel: Option<u8>,
+
+    /// The target note numbers (all notes if not given)
+    #[arg(long, value_delimiter = ',')]
+    pub target_notes: Vec<u8>,
+
+    /// Give every target note its own target channel (voice)
+    #[arg(long, default_value_t = false)]
+    pub voices: bool,
+
+    /// Subdivisions of a beat at which the absence of a target onset is trained
+    #[arg(long, default_value_t = 4)]
+    pub resolution: u32,
+}
+
+#[derive(Args, Debug, Serialize, Deserialize, Default)]
+pub struct FromSessionArgs {
+    /// The name of the recorded session
+    pub session: String,
+
+    /// The input note numbers (all notes if not given)
+    #[arg(long, value_delimiter = ',')]
+    pub input_notes: Vec<u8>,
+
+    /// Subdivisions of a beat at which the absence of an output note is trained
+    #[arg(long, default_value_t = 4)]
+    pub resolution: u32,
+}
+
+/// Record the MIDI input, metronome tempo, model output and output notes of a live session
+#[derive(Args, Debug)]
+pub struct RecordArgs {
+    /// The name of the session
+    pub name: String,
+
+    /// Stop recording after this many seconds (records until interrupted if not given)
+    #[arg(short, long)]
+    pub duration: Option<f64>,
+
+    /// Overwrite an existing session with the same name
+    #[arg(long, default_value_t = false)]
+    pub force: bool,
+
+    /// Port on which the midi inputs come in
+    #[arg(long, default_value_t = MIDI_PORT)]
+    pub midi_port: u16,
+
+    /// Port on which the output notes come in
+    #[arg(long, default_value_t = OUTPUT_PORT)]
+    pub output_port: u16,
+
+    /// Port on which the metronome tempo comes in
+    #[arg(long, default_value_t = METRONOME_PORT)]
+    pub metronome_port: u16,
+
+    /// Port on which the network output comes in
+    #[arg(long, default_value_t = FEEL_PORT)]
+    pub network_port: u16,
+}
+
 #[derive(Args, Debug)]
 pub struct CompletionsArgs {
     /// The shell for which to generate completions (only zsh works)
@@ -562,6 +1051,28 @@ impl Default for ArpeggioArgs {
 #[derive(Args, Debug)]
 pub struct TuiArgs {}
 
+#[derive(Args, Debug)]
+pub struct ModelsArgs {
+    /// what to do with the stored models
+    #[command(subcommand)]
+    pub command: ModelsCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ModelsCommand {
+    /// Export a reservoir as npy files with a metadata TOML, which can be loaded with `--npy`
+    Export(ExportArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct ExportArgs {
+    /// The name of the model to export
+    pub name: String,
+
+    /// The directory to write the npy files and `metadata.toml` to
+    pub dir: PathBuf,
+}
+
 #[derive(Args, Debug)]
 pub struct DevArgs {
     /// the test to run
@@ -573,6 +1084,8 @@ pub struct DevArgs {
 pub enum DevCommand {
     Robot(RobotArgs),
     Hyper(HyperArgs),
+    /// Analyze the dynamics of a trained reservoir
+    Analyze(AnalyzeArgs),
 }
 
 #[derive(Args, Debug)]
@@ -594,6 +1107,32 @@ pub struct HyperArgs {
     /// path to parameter file
     #[arg(short, long)]
     pub path: PathBuf,
+
+    /// Seed from which the seed of every trained model is derived
+    #[arg(long)]
+    pub seed: Option<u64>,
+}
+
+#[derive(Args, Debug)]
+pub struct AnalyzeArgs {
+    /// The name of the model to analyze
+    pub model: String,
+
+    /// The directory to write the results to
+    #[arg(short, long, default_value = "data/analysis")]
+    pub output: PathBuf,
+
+    /// The largest input delay of the memory capacity
+    #[arg(long, default_value_t = 100)]
+    pub max_delay: usize,
+
+    /// The amount of timesteps of random input for the memory capacity
+    #[arg(long, default_value_t = 5000)]
+    pub steps: usize,
+
+    /// Seed of the random input for the memory capacity
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
 }
 
 #[derive(Args, Debug)]
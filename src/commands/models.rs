@@ -0,0 +1,29 @@
+use std::error::Error;
+
+use super::{ExportArgs, ModelsArgs, ModelsCommand};
+use crate::model::StoredModel;
+
+pub fn models(args: ModelsArgs) -> Result<(), Box<dyn Error>> {
+    match args.command {
+        ModelsCommand::Export(e) => export(e),
+    }
+}
+
+/// Export a stored reservoir to npy files, e.g. for analysis in Python
+fn export(args: ExportArgs) -> Result<(), Box<dyn Error>> {
+    let nw = match StoredModel::load_from_name(&args.name)? {
+        StoredModel::Reservoir(nw) => nw,
+        _ => {
+            return Err(format!(
+                "Model `{}` is not a (single) reservoir, only those can be exported",
+                args.name
+            )
+            .into())
+        }
+    };
+
+    let metadata = nw.export_npy(&args.dir)?;
+    println!("Exported `{}` to {}", args.name, metadata.display());
+
+    Ok(())
+}
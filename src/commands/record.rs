@@ -0,0 +1,102 @@
+use std::{
+    error::Error,
+    io::{BufWriter, Write},
+    time::Instant,
+};
+
+use super::RecordArgs;
+use crate::{
+    errors::NeuronError,
+    messages::MidiNoteMessage,
+    session::{session_path, SessionEvent, SessionMessage},
+};
+
+/// Time to wait for messages before checking if the recording is over [ms]
+const POLL_TIMEOUT: i64 = 100;
+
+/// Convert a message from one of the sockets into a session message
+fn parse(socket: usize, bytes: &[u8]) -> Result<SessionMessage, Box<dyn Error>> {
+    Ok(match socket {
+        // the output notes are published on their own port, but the MIDI port may have them too
+        0 | 1 => {
+            let msg = MidiNoteMessage::from_bytes(bytes)?;
+            match (msg.velocity(), msg) {
+                (Some(velocity), MidiNoteMessage::InputNotes(notes, _)) => {
+                    SessionMessage::Input { notes, velocity }
+                }
+                _ => SessionMessage::Output,
+            }
+        }
+        2 => SessionMessage::Tempo(f64::from_be_bytes(bytes.try_into()?)),
+        _ => SessionMessage::Feel(f32::from_be_bytes(bytes.try_into()?) as f64),
+    })
+}
+
+/// Record everything that is published on the MIDI, output, metronome and feel sockets into a
+/// session file, until the duration is over or the process is interrupted.
+pub fn record(args: RecordArgs) -> Result<(), Box<dyn Error>> {
+    let path = session_path(&args.name)?;
+    if path.exists() && !args.force {
+        return Err(NeuronError::AlreadyExists(format!("Session {}", args.name)).into());
+    }
+
+    let context = zmq::Context::new();
+    let ports = [
+        args.midi_port,
+        args.output_port,
+        args.metronome_port,
+        args.network_port,
+    ];
+    let sockets = ports
+        .iter()
+        .map(|port| {
+            let socket = context.socket(zmq::SUB)?;
+            socket.connect(&format!("ipc:///tmp/zmq_robodrummer_{}", port))?;
+            socket.set_subscribe(b"")?;
+            Ok(socket)
+        })
+        .collect::<Result<Vec<zmq::Socket>, zmq::Error>>()?;
+
+    let mut writer = BufWriter::new(std::fs::File::create(&path)?);
+    println!(
+        "Recording session `{}` to {}, press Ctrl-C to stop",
+        args.name,
+        path.display()
+    );
+
+    let start = Instant::now();
+    let mut count = 0;
+    while args
+        .duration
+        .is_none_or(|d| start.elapsed().as_secs_f64() < d)
+    {
+        let mut items: Vec<zmq::PollItem> = sockets
+            .iter()
+            .map(|s| s.as_poll_item(zmq::POLLIN))
+            .collect();
+        if zmq::poll(&mut items, POLL_TIMEOUT)? == 0 {
+            continue;
+        }
+
+        for (i, socket) in sockets.iter().enumerate() {
+            while let Ok(bytes) = socket.recv_bytes(zmq::DONTWAIT) {
+                let time = start.elapsed().as_secs_f64() * 1000.0;
+                match parse(i, &bytes) {
+                    Ok(message) => {
+                        let event = SessionEvent { time, message };
+                        serde_json::to_writer(&mut writer, &event)?;
+                        writeln!(writer)?;
+                        count += 1;
+                    }
+                    Err(e) => log::warn!("Skipping a message from port {}: {}", ports[i], e),
+                }
+            }
+        }
+
+        // keep the file complete, as the recording is usually stopped by an interrupt
+        writer.flush()?;
+    }
+
+    println!("Recorded {} messages", count);
+    Ok(())
+}
@@ -1,14 +1,17 @@
 use std::{
+    collections::HashMap,
     error::Error,
     sync::{mpsc::Sender, Arc, Mutex},
     time::{Duration, Instant},
 };
 
 use crate::{
-    data::{get_model_metadata, list_models},
+    data::{get_model_metadata, list_models, snapshots_dir},
+    encoding::InputEncoder,
     guier::Gui,
-    messages::{MidiNoteMessage, NetworkMessage},
-    reservoir::Reservoir,
+    messages::{ControlMessage, MidiNoteMessage, NetworkMessage, StateMessage},
+    model::{load_model, load_realtime_model, RhythmModel, Snapshot},
+    reservoir::readout::check_forgetting,
 };
 use ndarray::Array1;
 
@@ -22,16 +25,27 @@ pub fn run(
         return list_models();
     }
 
+    if args.adapt {
+        check_forgetting(args.forgetting)?;
+    }
+
     // get parsed arguments
     let model = args.model;
     let zmq_port_pub = args.network_port;
     let zmq_port_sub = args.metronome_port;
 
-    // open the selected network
-    let mut nw = Reservoir::load_from_name(&model)?;
+    // open the selected network, whatever kind of model it is
+    // adapting needs the trainable model, otherwise the allocation-free inference path is used
+    if args.adapt && args.f32 {
+        log::warn!("Adapting runs in double precision, ignoring --f32");
+    }
+    let mut nw = match args.adapt {
+        true => load_model(&model)?,
+        false => load_realtime_model(&model, args.f32)?,
+    };
+    log::info!("Running {}", nw.metadata());
 
-    // generate the sparse representation for efficient multiplication
-    nw.generate_sparse();
+    let metadata = get_model_metadata(&model)?;
 
     // set up network output connection
     let context = zmq::Context::new();
@@ -56,6 +70,33 @@ pub fn run(
     midi_in.connect(&format!("ipc:///tmp/zmq_robodrummer_{}", args.midi_port))?;
     midi_in.set_subscribe(b"")?;
 
+    // listen for output notes, which are the teacher signal when adapting
+    let output_in = context.socket(zmq::SUB)?;
+    if args.adapt {
+        output_in.connect(&format!("ipc:///tmp/zmq_robodrummer_{}", args.output_port))?;
+        output_in.set_subscribe(b"")?;
+    }
+
+    // publish the model state, e.g. for visualizations
+    let state_publisher = context.socket(zmq::PUB)?;
+    if args.state_every > 0 {
+        state_publisher.bind(&format!("ipc:///tmp/zmq_robodrummer_{}", args.state_port))?;
+    }
+
+    // listen for snapshot and recall requests, only when asked for so that multiple instances
+    // can run side by side
+    let control_in = context.socket(zmq::SUB)?;
+    if args.control {
+        control_in.bind(&format!("ipc:///tmp/zmq_robodrummer_{}", args.control_port))?;
+        control_in.set_subscribe(b"")?;
+    }
+
+    // snapshots taken while running, they are also saved to disk
+    let mut snapshots: HashMap<String, Snapshot> = HashMap::new();
+    if let Some(name) = &args.restore {
+        nw.restore(&load_snapshot(&model, name, &mut snapshots)?)?;
+    }
+
     // set up osc output Socket
     // let osc_sock = oscutil::create_socket(args.osc_port)?;
 
@@ -67,31 +108,61 @@ pub fn run(
     }
     gui.show();
 
-    // get the input width
-    let metadata = get_model_metadata(&model)?;
-    let input_width = metadata.width;
-    let mut input_steps_remaining = 0;
+    // encode the inputs the same way as during training
+    let mut encoder = InputEncoder::new(metadata.encoding, metadata.width);
+
+    // online adaptation of the readout
+    let mut rls = match (args.adapt, nw.readout_mut()) {
+        (false, _) => None,
+        (true, Some(readout)) => Some(readout.new_rls(args.forgetting)),
+        (true, None) => return Err("This model can't be adapted while running".into()),
+    };
+    let mut target_steps_remaining = 0;
+
+    let mut input = Array1::zeros(nw.metadata().inputs);
+    let mut step: u64 = 0;
 
     // main loop
     loop {
         let start = Instant::now();
 
-        // input zero on non-inputs, 1 on inputs
-        let mut input = Array1::zeros(nw.inputs);
+        if let Ok(msg) = control_in.recv_bytes(zmq::DONTWAIT) {
+            if let Err(e) = control(&msg, nw.as_mut(), &model, &mut snapshots) {
+                log::warn!("Control request failed: {}", e);
+            }
+        }
+
         if let Ok(midi_msg) = midi_in.recv_bytes(zmq::DONTWAIT) {
             let msg: MidiNoteMessage = bincode::deserialize(&midi_msg)?;
-            if msg.is_input() {
-                input_steps_remaining = input_width;
+            if let Some(velocity) = msg.velocity() {
+                encoder.hit(velocity);
             }
         }
 
-        if input_steps_remaining > 0 {
-            input_steps_remaining -= 1;
-            input[0] = 1.0;
-        }
+        input[0] = encoder.step();
 
         // apply one timestep
-        nw.forward(&input);
+        nw.step(&input);
+
+        if let Some(rls) = &mut rls {
+            // the first output should fire while the output notes are played
+            if let Ok(msg) = output_in.recv_bytes(zmq::DONTWAIT) {
+                if let MidiNoteMessage::OutputNote = MidiNoteMessage::from_bytes(&msg)? {
+                    target_steps_remaining = metadata.target_width;
+                }
+            }
+
+            let mut target = nw.read_outputs().to_owned();
+            target[0] = match target_steps_remaining > 0 {
+                true => 1.0,
+                false => 0.0,
+            };
+            target_steps_remaining = target_steps_remaining.saturating_sub(1);
+
+            if let Some(readout) = nw.readout_mut() {
+                readout.rls_step(rls, &target);
+            }
+        }
 
         let adjusted_timestep = {
             let m = metronome.lock().unwrap();
@@ -101,8 +172,19 @@ pub fn run(
         };
 
         // show and publish output
-        let new_output = nw.get_output(0);
+        let new_output = nw.read_outputs()[0];
         publisher.send((new_output as f32).to_be_bytes().as_slice(), 0)?;
+
+        if args.state_every > 0 && step.is_multiple_of(args.state_every as u64) {
+            let snapshot = nw.snapshot();
+            let msg = StateMessage {
+                step,
+                state: snapshot.decimated(args.state_size),
+                outputs: snapshot.output.iter().map(|o| *o as f32).collect(),
+            };
+            state_publisher.send(bincode::serialize(&msg)?, zmq::DONTWAIT)?;
+        }
+        step += 1;
         // oscutil::send_osc_msg(
         //     "/robodrummer",
         //     vec![rosc::OscType::Float(new_output as f32)],
@@ -139,3 +221,43 @@ pub fn run(
 
     Ok(())
 }
+
+/// Handle a snapshot or recall request
+fn control(
+    msg: &[u8],
+    nw: &mut dyn RhythmModel,
+    model: &str,
+    snapshots: &mut HashMap<String, Snapshot>,
+) -> Result<(), Box<dyn Error>> {
+    match bincode::deserialize(msg)? {
+        ControlMessage::Snapshot(name) => {
+            let snapshot = nw.snapshot();
+            snapshot.save_to_file(snapshots_dir(model)?.join(format!("{}.bin", name)))?;
+            log::info!("Saved snapshot {}", name);
+            snapshots.insert(name, snapshot);
+        }
+        ControlMessage::Recall(name) => {
+            nw.restore(&load_snapshot(model, &name, snapshots)?)?;
+            log::info!("Recalled snapshot {}", name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Get a snapshot from memory, or from disk if it was taken in an earlier run
+fn load_snapshot(
+    model: &str,
+    name: &str,
+    snapshots: &mut HashMap<String, Snapshot>,
+) -> Result<Snapshot, Box<dyn Error>> {
+    if let Some(snapshot) = snapshots.get(name) {
+        return Ok(snapshot.clone());
+    }
+
+    let snapshot = Snapshot::load_from_file(snapshots_dir(model)?.join(format!("{}.bin", name)))
+        .map_err(|e| format!("Can't load snapshot {}: {}", name, e))?;
+    snapshots.insert(name.to_string(), snapshot.clone());
+
+    Ok(snapshot)
+}
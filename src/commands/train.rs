@@ -1,28 +1,68 @@
-use std::error::Error;
+use std::{error::Error, path::PathBuf};
 
 use crate::{
-    commands::TrainMode,
-    data::{list_data, load_train_data, models_dir},
-    reservoir::Reservoir,
+    augmentation::Augmentation,
+    commands::{ModelKind, Topology, TrainMode},
+    data::{
+        list_data, load_extra_data, load_train_data, models_dir, read_train_data, target_channels,
+        Data,
+    },
+    errors::NeuronError,
+    evaluation::{evaluate, OnsetMetrics},
+    model::StoredModel,
+    nvar::Nvar,
+    plot::plot_csv,
+    reservoir::{
+        readout::{check_forgetting, CrossValidation, Sequence},
+        DeepReservoir, LinearReadout, Reservoir,
+    },
     trainutil::create_progress_bar,
 };
-use make_csv::{csv_entry, csv_start, python};
+use make_csv::{csv_entry, csv_start};
 use ndarray::{Array1, Array2};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Serialize;
 use text_io::try_read;
 
+/// Summary of a training run, written with `--report`
+#[derive(Serialize)]
+struct TrainReport<'a> {
+    data: &'a str,
+    /// the name of the saved model, if it was saved
+    model: Option<&'a str>,
+    model_path: Option<PathBuf>,
+    metadata_path: Option<PathBuf>,
+    seed: Option<u64>,
+    iterations: usize,
+    final_error: Option<f64>,
+    lowest_error: Option<f64>,
+    regularization: f64,
+    evaluation: &'a Option<OnsetMetrics>,
+    cross_validation: &'a Option<CrossValidation>,
+}
+
+/// Fail before training when the model can't be saved under the given name
+fn check_model_name(name: &str, force: bool) -> Result<(), Box<dyn Error>> {
+    if !force && StoredModel::path(name)?.exists() {
+        return Err(NeuronError::AlreadyExists(format!("Model {}", name)).into());
+    }
+    Ok(())
+}
+
+/// Save the model and its metadata, returning the paths of both
 fn save_trained_model(
-    nw: &mut Reservoir,
+    nw: &mut dyn LinearReadout,
     name: &str,
     args: &super::TrainArgs,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(PathBuf, PathBuf), Box<dyn Error>> {
     println!("Saving model as {}", name);
     nw.reset_state();
-    nw.save(name)?;
+    nw.save(name, Some(args))?;
     let meta_path = models_dir()?.join(format!("{}.toml", name));
     let metadata = toml::to_string(&args)?;
-    std::fs::write(meta_path, metadata)?;
+    std::fs::write(&meta_path, metadata)?;
 
-    Ok(())
+    Ok((StoredModel::path(name)?, meta_path))
 }
 
 fn analyze(
@@ -30,27 +70,34 @@ fn analyze(
     test_inputs: &[Array1<f64>],
     targets: &[Option<Array1<f64>>],
     errors: &[f64],
-    nw: &mut Reservoir,
+    nw: &mut dyn LinearReadout,
     args: &super::TrainArgs,
-) {
+) -> Result<(), Box<dyn Error>> {
     {
         // plot target and network output graph
         let mut wtr = csv_start!("data/network_trained.csv");
-        csv_entry!(wtr <- "t", "nw_0", "target_0", "input_0");
+        let mut header = vec!["t".to_string()];
+        for o in 0..args.outputs {
+            header.push(format!("nw_{}", o));
+            header.push(format!("target_{}", o));
+        }
+        header.push("input_0".to_string());
+        wtr.write_record(header)?;
 
-        (0..train_inputs.len()).for_each(|i| {
+        for i in 0..train_inputs.len() {
             nw.forward(&train_inputs[i]);
-            match &targets[i] {
-                Some(t) => {
-                    csv_entry!(wtr <- i, nw.output[0], t[0], train_inputs[i][0]);
-                }
-                None => {
-                    csv_entry!(wtr <- i, nw.output[0], "", train_inputs[i][0]);
-                }
+            let mut record = vec![i.to_string()];
+            for o in 0..args.outputs {
+                record.push(nw.output()[o].to_string());
+                record.push(match &targets[i] {
+                    Some(t) => t[o].to_string(),
+                    None => "".to_string(),
+                });
             }
-        });
+            record.push(train_inputs[i][0].to_string());
+            wtr.write_record(record)?;
+        }
     }
-    python!("plot.py", "data/network_trained.csv");
 
     {
         // plot error graph
@@ -61,50 +108,77 @@ fn analyze(
             csv_entry!(wtr <- i, error);
         }
     }
-    python!("plot.py", "data/error.csv");
 
     {
         // plot test graph
         let mut wtr = csv_start!("data/network_test.csv");
         let mut int_wtr = csv_start!("data/int_states.csv");
 
-        csv_entry!(wtr <- "t", "nw_0", "input_0");
+        let mut header: Vec<String> = (0..args.outputs).map(|o| format!("nw_{}", o)).collect();
+        header.insert(0, "t".to_string());
+        header.push("input_0".to_string());
+        wtr.write_record(header)?;
         csv_entry!(int_wtr <- "t", "state_0", "state_1", "state_2", "state_3");
 
         nw.reset_state();
 
-        for (i, input) in test_inputs.iter().enumerate() {
+        let zeros: Array1<f64> = Array1::zeros(args.inputs);
+
+        // also add some zeros to see the steady state behaviour
+        let inputs = test_inputs.iter().chain(std::iter::repeat_n(&zeros, 1000));
+        for (i, input) in inputs.enumerate() {
             nw.forward(input);
 
-            csv_entry!(wtr <- i, nw.output[0], input[0]);
-            let states = nw.get_visible_state();
-            csv_entry!(int_wtr <- i, states[0], states[10], states[20], states[35]);
+            let mut record = vec![i.to_string()];
+            record.extend(nw.output().iter().map(|o| o.to_string()));
+            record.push(input[0].to_string());
+            wtr.write_record(record)?;
+
+            // not every model has as many states (e.g. NVAR features)
+            let states = nw.readout_state();
+            let state = |i: usize| states[i.min(states.len() - 1)];
+            csv_entry!(int_wtr <- i, state(0), state(10), state(20), state(35));
         }
+    }
 
-        // also add some zeros to see the steady state behaviour
-        for i in 0..1000 {
-            let input: Array1<f64> = Array1::zeros(args.inputs);
-            nw.forward(&input);
-            csv_entry!(wtr <- i + test_inputs.len(), nw.output[0], input[0]);
-            let states = nw.get_visible_state();
-            csv_entry!(int_wtr <- i, states[0], states[10], states[20], states[35]);
+    // a failing plot shouldn't cost the trained model
+    for name in ["network_trained", "error", "network_test", "int_states"] {
+        let svg = format!("data/{}.svg", name);
+        match plot_csv(format!("data/{}.csv", name), &svg) {
+            Ok(()) => log::info!("Plotted {}", svg),
+            Err(e) => log::warn!("Could not plot {}: {}", svg, e),
         }
     }
-    python!("plot.py", "data/network_test.csv");
-    python!("plot.py", "data/int_states.csv");
+
+    Ok(())
 }
 
-pub fn train(args: super::TrainArgs) -> Result<(), Box<dyn Error>> {
+pub fn train(mut args: super::TrainArgs) -> Result<(), Box<dyn Error>> {
     if args.list_data {
         list_data()?;
         return Ok(());
     }
 
-    log::info!("Training arguments: {:#?}", args);
-
-    let mut nw = Reservoir::from_args(&args);
+    if let Some(name) = &args.save_as {
+        check_model_name(name, args.force)?;
+    }
 
-    nw.generate_sparse();
+    if !(0.0..=1.0).contains(&args.rewire) {
+        return Err(format!(
+            "The rewire probability should be in [0, 1], got {}",
+            args.rewire
+        )
+        .into());
+    }
+    // a jump of 1 would only replace the ring by a symmetric one
+    if args.topology == Topology::CycleJumps && !(2..args.size).contains(&args.jump) {
+        return Err(format!(
+            "The jump distance should be at least 2 and smaller than the size ({}), got {}",
+            args.size, args.jump
+        )
+        .into());
+    }
+    check_forgetting(args.forgetting)?;
 
     // TODO: get the input data from a file
     let shift = match args.shift {
@@ -118,32 +192,126 @@ pub fn train(args: super::TrainArgs) -> Result<(), Box<dyn Error>> {
         args.width,
         args.target_width,
         shift,
+        args.encoding,
     )?;
 
+    // the model gets one output per target channel (voice) in the data
+    let channels = target_channels(&targets);
+    if channels != args.outputs {
+        log::info!("Using {} outputs, one per target channel", channels);
+        args.outputs = channels;
+    }
+
+    // make sure that this model can be reproduced from its metadata
+    let seed = *args.seed.get_or_insert_with(rand::random);
+    log::info!("Using seed {}", seed);
+
+    log::info!("Training arguments: {:#?}", args);
+
+    let mut nw: Box<dyn LinearReadout> = match (args.model, args.layers) {
+        (ModelKind::Nvar, _) => Box::new(Nvar::from_args(&args)),
+        (ModelKind::Esn, 0 | 1) => Box::new(Reservoir::from_args(&args)),
+        (ModelKind::Esn, _) => Box::new(DeepReservoir::from_args(&args)),
+    };
+
     // get data and perform splits
     let train_len = (inputs.len() as f64 * args.split) as usize;
     let train_inputs = &inputs[0..train_len];
-    let test_inputs = &inputs[(inputs.len() as f64 * args.split) as usize..];
+    let test_inputs = &inputs[train_len..];
+    let test_targets = &targets[train_len..];
     let targets = &targets[0..train_len];
 
+    // the additional datasets are independent sequences, only used for training
+    let extra_data = load_extra_data(&args)?;
+    let mut sequences: Vec<Sequence> = vec![(train_inputs, targets)];
+    sequences.extend(extra_data.iter().map(|(i, t)| (i.as_slice(), t.as_slice())));
+
+    // with augmentation, every iteration trains on a new rendering of the time-domain data
+    let augmentation = Augmentation::from_args(&args)?;
+    let raw_data = match augmentation {
+        Some(_) => std::iter::once(&args.data)
+            .chain(args.extra_data.iter())
+            .map(|name| read_train_data(name))
+            .collect::<Result<Vec<_>, _>>()?,
+        None => vec![],
+    };
+    let mut rng = StdRng::seed_from_u64(seed);
+
     let pb = create_progress_bar("Training...", args.iter);
 
     let mut errors = Vec::with_capacity(args.iter as usize);
 
     // keep history of output weights to jump back to a previous better version
     // to get rid of the weird training behaviour (which will need to be investigated further)
-    let mut weight_history: Array2<f64> = Array2::zeros(nw.weights_res_out.dim());
-    let mut best_weights = nw.weights_res_out.clone();
+    let mut weight_history: Array2<f64> = Array2::zeros(nw.readout_weights().dim());
+    let mut best_weights = nw.readout_weights().clone();
     let mut lowest_error = std::f64::MAX;
     let mut last_error = 0.0;
 
+    // select the regularization parameter on held-out folds of the training data
+    if let Some(folds) = args.folds {
+        // the folds are scored with ridge regression, so the readout is trained with it too
+        if !matches!(args.mode, TrainMode::Ridge) {
+            println!(
+                "Cross-validation implies ridge regression, ignoring `--mode {}`",
+                args.mode
+            );
+            args.mode = TrainMode::Ridge;
+        }
+        let cv = nw.cross_validate(&sequences, folds, &args.cv_lambdas())?;
+        println!(
+            "Cross-validation selected regularization \x1b[1m{:e}\x1b[0m (mean fold error {:.5})",
+            cv.lambda,
+            cv.fold_scores.iter().sum::<f64>() / cv.folds as f64
+        );
+        args.regularization = cv.lambda;
+        args.cross_validation = Some(cv);
+    }
+
+    // the recursive least squares state is kept over all iterations
+    let mut rls = nw.new_rls(args.forgetting);
+
     for i in 0..args.iter {
         // save the history before any adjustments
-        weight_history.assign(&nw.weights_res_out);
+        weight_history.assign(nw.readout_weights());
+
+        let augmented: Vec<Data> = match &augmentation {
+            Some(augmentation) => raw_data
+                .iter()
+                .map(|data| {
+                    augmentation.apply(data, &mut rng).to_timesteps(
+                        args.timestep,
+                        args.width,
+                        args.target_width,
+                        shift,
+                        args.encoding,
+                    )
+                })
+                .collect(),
+            None => vec![],
+        };
+        let epoch: Vec<Sequence> = if augmented.is_empty() {
+            sequences.clone()
+        } else {
+            augmented
+                .iter()
+                .enumerate()
+                .map(|(i, (inputs, targets))| {
+                    // the test split of the main data is never trained on
+                    let len = match i {
+                        0 => (inputs.len() as f64 * args.split) as usize,
+                        _ => inputs.len(),
+                    };
+                    (&inputs[..len], &targets[..len])
+                })
+                .collect()
+        };
 
         let error = match args.mode {
-            TrainMode::Inv => nw.train_step(train_inputs, targets, (i as usize * 15) % 31),
-            TrainMode::Grad => nw.train_mse_grad(train_inputs, targets),
+            TrainMode::Inv => nw.train_step(&epoch),
+            TrainMode::Grad => nw.train_mse_grad(&epoch),
+            TrainMode::Ridge => nw.train_ridge(&epoch)?,
+            TrainMode::Rls => nw.train_rls(&epoch, &mut rls),
         };
 
         // important...
@@ -174,23 +342,60 @@ pub fn train(args: super::TrainArgs) -> Result<(), Box<dyn Error>> {
     nw.set_weights_out(best_weights);
 
     let weights_sum = nw
-        .weights_res_out
+        .readout_weights()
         .iter()
         .map(|x| x.abs())
         .fold(0.0, |acc, x| acc + x);
 
     log::info!("Sum of output weights: {}", weights_sum);
 
-    analyze(train_inputs, test_inputs, targets, &errors, &mut nw, &args);
+    // check whether the outputs produce onsets at the right times
+    let metrics = evaluate(nw.as_mut(), test_inputs, test_targets, &args);
+    println!("Test onsets: \x1b[1m{}\x1b[0m", metrics);
+    args.evaluation = Some(metrics);
+
+    analyze(
+        train_inputs,
+        test_inputs,
+        targets,
+        &errors,
+        nw.as_mut(),
+        &args,
+    )?;
 
-    print!("Save this model? [filename]: ");
-    let answer: Result<String, _> = try_read!();
-    let Ok(name) = answer else {
-        return Ok(());
+    let name = match &args.save_as {
+        Some(name) => Some(name.clone()),
+        None => {
+            print!("Save this model? [filename]: ");
+            let answer: Result<String, _> = try_read!();
+            answer.ok()
+        }
     };
 
     // nw.generate_sparse();
-    save_trained_model(&mut nw, &name, &args)?;
+    let paths = match &name {
+        Some(name) => Some(save_trained_model(nw.as_mut(), name, &args)?),
+        None => None,
+    };
+
+    if let Some(report_path) = &args.report {
+        let (model_path, metadata_path) = paths.unzip();
+        let report = TrainReport {
+            data: &args.data,
+            model: name.as_deref(),
+            model_path,
+            metadata_path,
+            seed: args.seed,
+            iterations: errors.len(),
+            final_error: errors.last().copied(),
+            lowest_error: errors.iter().copied().reduce(f64::min),
+            regularization: args.regularization,
+            evaluation: &args.evaluation,
+            cross_validation: &args.cross_validation,
+        };
+        std::fs::write(report_path, serde_json::to_string_pretty(&report)?)?;
+        println!("Report written to {}", report_path.display());
+    }
 
     Ok(())
 }
@@ -3,3 +3,8 @@
 */
 
 pub const OUTPUT_NEURON_DIRECT_FEEDBACK: bool = true;
+
+/// Random reservoirs with more neurons than this are created directly in the sparse representation,
+/// and their spectral radius is estimated using power iteration instead of a dense eigenvalue
+/// decomposition.
+pub const SPARSE_RESERVOIR_SIZE: usize = 1000;
@@ -3,12 +3,81 @@ use std::{collections::VecDeque, error::Error, io::Read};
 use ndarray::{array, Array1};
 use serde::{Deserialize, Serialize};
 
-use crate::commands::{GenerateDataArgs, RhythmAlgorithm, TrainArgs};
-
-#[derive(Serialize, Deserialize)]
+use crate::{
+    augmentation::Augmentation,
+    commands::{GenerateDataArgs, ModelKind, RhythmAlgorithm, TempoCurve, TrainArgs},
+    encoding::{InputEncoder, InputEncoding},
+    model::ModelFile,
+};
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct TrainData {
-    pub inputs: VecDeque<(f64, bool)>,
-    pub targets: VecDeque<(f64, bool)>,
+    /// Input hit instants, with the velocity (0 to 1) of each hit
+    pub inputs: VecDeque<(f64, f64)>,
+    /// Target instants, with one onset flag per target channel (voice)
+    pub targets: VecDeque<(f64, Vec<bool>)>,
+}
+
+/// Magic bytes at the start of a versioned train data file
+const TRAIN_DATA_MAGIC: [u8; 4] = *b"RDTD";
+/// The current version of the train data file format
+const TRAIN_DATA_VERSION: u16 = 1;
+
+/// Train data as it was stored before the header, with a single target channel and no velocities
+#[derive(Deserialize)]
+struct SingleTargetTrainData {
+    inputs: VecDeque<(f64, bool)>,
+    targets: VecDeque<(f64, bool)>,
+}
+
+impl From<SingleTargetTrainData> for TrainData {
+    fn from(legacy: SingleTargetTrainData) -> Self {
+        TrainData {
+            // every recorded input was a full-velocity hit
+            inputs: legacy
+                .inputs
+                .into_iter()
+                .map(|(time, _)| (time, 1.0))
+                .collect(),
+            targets: legacy
+                .targets
+                .into_iter()
+                .map(|(time, onset)| (time, vec![onset]))
+                .collect(),
+        }
+    }
+}
+
+impl TrainData {
+    /// Serialize the train data, prefixed with a header containing the format version
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut bytes = TRAIN_DATA_MAGIC.to_vec();
+        bytes.extend_from_slice(&TRAIN_DATA_VERSION.to_le_bytes());
+        bytes.extend(bincode::serialize(self)?);
+        Ok(bytes)
+    }
+
+    /// Deserialize train data, also accepting data stored by older versions of this program
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if let Some(body) = bytes.strip_prefix(&TRAIN_DATA_MAGIC) {
+            if body.len() < 2 {
+                return Err("Train data file is truncated".into());
+            }
+            let version = u16::from_le_bytes([body[0], body[1]]);
+            if version != TRAIN_DATA_VERSION {
+                return Err(format!("Unsupported train data version {}", version).into());
+            }
+            return Ok(bincode::deserialize(&body[2..])?);
+        }
+
+        let legacy: SingleTargetTrainData = bincode::deserialize(bytes)?;
+        Ok(legacy.into())
+    }
+
+    /// The amount of target channels in this data
+    pub fn channels(&self) -> usize {
+        self.targets.front().map(|(_, t)| t.len()).unwrap_or(0)
+    }
 }
 
 const TRAIN_DATA_HEIGHT: f64 = 1.0;
@@ -38,6 +107,30 @@ pub fn models_dir() -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
     Ok(path)
 }
 
+pub fn snapshots_dir(model: &str) -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
+    // snapshots are kept per model, as they only fit the model they were taken from
+    let mut path = robodrummer_dir()?;
+    path.push("snapshots");
+    path.push(model);
+
+    if !path.exists() {
+        std::fs::create_dir_all(&path)?;
+    }
+
+    Ok(path)
+}
+
+pub fn sessions_dir() -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
+    let mut path = robodrummer_dir()?;
+    path.push("sessions");
+
+    if !path.exists() {
+        std::fs::create_dir_all(&path)?;
+    }
+
+    Ok(path)
+}
+
 pub fn data_dir() -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
     // get the data dir for this app
     let mut path = robodrummer_dir()?;
@@ -60,9 +153,18 @@ fn show_model_meta(metadata: &TrainArgs) -> String {
         "\t- mode: \x1b[38;5;33m{}\x1b[0m\n",
         metadata.mode
     ));
-    let structure = match metadata.npy {
-        Some(_) => "Euler ESN",
-        None => "Random ESN",
+    let structure = match (&metadata.npy, metadata.grid) {
+        (Some(_), _) => "Euler ESN".to_string(),
+        (None, true) => "Grid ESN".to_string(),
+        (None, false) => metadata.topology.to_string(),
+    };
+    let structure = match (metadata.model, metadata.layers) {
+        (ModelKind::Nvar, _) => format!(
+            "NVAR, {} delays of {} ts, order {}",
+            metadata.delays, metadata.stride, metadata.order
+        ),
+        (ModelKind::Esn, 0 | 1) => structure,
+        (ModelKind::Esn, layers) => format!("Deep ESN, {} x {}", layers, structure),
     };
     output.push_str(&format!(
         "\t- structure: \x1b[38;5;33m{}\x1b[0m\n",
@@ -80,6 +182,14 @@ fn show_model_meta(metadata: &TrainArgs) -> String {
         "\t- timestep: \x1b[38;5;33m{} ms\x1b[0m\n",
         metadata.timestep
     ));
+    output.push_str(&format!(
+        "\t- input encoding: \x1b[38;5;33m{:?}\x1b[0m\n",
+        metadata.encoding
+    ));
+    output.push_str(&format!(
+        "\t- outputs: \x1b[38;5;33m{}\x1b[0m\n",
+        metadata.outputs
+    ));
     output.push_str(&format!(
         "\t- target width: \x1b[38;5;33m{} ts\x1b[0m\n",
         metadata.target_width
@@ -88,6 +198,37 @@ fn show_model_meta(metadata: &TrainArgs) -> String {
         "\t- input width: \x1b[38;5;33m{} ts\x1b[0m\n",
         metadata.width
     ));
+    output.push_str(&format!(
+        "\t- input scaling: \x1b[38;5;33m{}\x1b[0m, bias scaling: \x1b[38;5;33m{}\x1b[0m\n",
+        metadata.input_scaling, metadata.bias_scaling
+    ));
+    if metadata.feedback {
+        output.push_str(&format!(
+            "\t- output feedback: \x1b[38;5;33menabled\x1b[0m (scaling {})\n",
+            metadata.feedback_scaling
+        ));
+    }
+    if let Ok(Some(a)) = Augmentation::from_args(metadata) {
+        output.push_str(&format!(
+            "\t- augmentation: \x1b[38;5;33mtempo ±{}, jitter {} ms, dropout {}, extra hits {}, shift ±{} ms\x1b[0m\n",
+            a.tempo, a.jitter, a.dropout, a.extra_hits, a.shift
+        ));
+    }
+    if let Some(seed) = metadata.seed {
+        output.push_str(&format!("\t- seed: \x1b[38;5;33m{}\x1b[0m\n", seed));
+    }
+    if let Some(metrics) = &metadata.evaluation {
+        output.push_str(&format!(
+            "\t- test onsets: \x1b[38;5;33m{}\x1b[0m\n",
+            metrics
+        ));
+    }
+    if let Some(cv) = &metadata.cross_validation {
+        output.push_str(&format!(
+            "\t- regularization: \x1b[38;5;33m{:e}\x1b[0m ({}-fold cv)\n",
+            cv.lambda, cv.folds
+        ));
+    }
     output
 }
 
@@ -99,7 +240,11 @@ pub fn get_model_metadata(name: &str) -> Result<TrainArgs, Box<dyn Error>> {
         let metadata: TrainArgs = toml::from_str(&toml_string)?;
         Ok(metadata)
     } else {
-        Err(format!("No metadata exists for model name {}", name).into())
+        // newer model files carry a copy of their metadata
+        ModelFile::load_from_name(name)
+            .ok()
+            .and_then(|file| file.train_args().ok().flatten())
+            .ok_or_else(|| format!("No metadata exists for model name {}", name).into())
     }
 }
 
@@ -158,10 +303,23 @@ fn show_data_meta(metadata: &GenerateDataArgs) -> String {
         "     - bpm: \x1b[38;5;12m{}\x1b[0m\n",
         metadata.bpm
     ));
+    if metadata.tempo_curve != TempoCurve::Constant {
+        let (first, last) = (metadata.tempo.first(), metadata.tempo.last());
+        output.push_str(&format!(
+            "     - tempo: \x1b[38;5;12m{}\x1b[0m ({:.1} to {:.1} bpm)\n",
+            metadata.tempo_curve,
+            first.map_or(metadata.bpm, |(_, bpm)| *bpm),
+            last.map_or(metadata.bpm, |(_, bpm)| *bpm)
+        ));
+    }
     output.push_str(&format!(
         "     - variance: \x1b[38;5;12m{}\x1b[0m\n",
         metadata.variance
     ));
+    output.push_str(&format!(
+        "     - velocity: \x1b[38;5;12m{}\x1b[0m (accent {})\n",
+        metadata.velocity, metadata.accent
+    ));
     output.push_str(&format!(
         "     - scale: \x1b[38;5;12m{}\x1b[0m",
         metadata.scale
@@ -233,6 +391,51 @@ pub fn list_data() -> Result<(), Box<dyn std::error::Error>> {
 
 pub type Data = (Vec<Array1<f64>>, Vec<Option<Array1<f64>>>);
 
+/// Load the additional datasets of the train arguments, which are used as independent sequences.
+///
+/// These use the same conversion parameters as the main dataset, and need the same amount of
+/// target channels.
+pub fn load_extra_data(args: &TrainArgs) -> Result<Vec<Data>, Box<dyn Error>> {
+    let shift = args
+        .shift
+        .map(|shift| (shift as f64 / args.timestep).round() as usize);
+
+    args.extra_data
+        .iter()
+        .map(|name| {
+            let data = load_train_data(
+                name,
+                args.timestep,
+                args.width,
+                args.target_width,
+                shift,
+                args.encoding,
+            )?;
+
+            let channels = target_channels(&data.1);
+            if channels != args.outputs {
+                return Err(format!(
+                    "Data `{}` has {} target channels, expected {}",
+                    name, channels, args.outputs
+                )
+                .into());
+            }
+
+            Ok(data)
+        })
+        .collect()
+}
+
+/// The amount of target channels (voices) in the loaded targets
+pub fn target_channels(targets: &[Option<Array1<f64>>]) -> usize {
+    targets
+        .iter()
+        .flatten()
+        .next()
+        .map(|t| t.len())
+        .unwrap_or(0)
+}
+
 /// Load the training data from a `.bin` file
 ///
 /// The training data is stored in a time-domain format,
@@ -244,13 +447,20 @@ pub type Data = (Vec<Array1<f64>>, Vec<Option<Array1<f64>>>);
 /// - `timestep`: The time between each timestep
 /// - `input_width`: The number of timesteps to consider as input
 /// - `shift`: the amount of timesteps to shift the target data into the future
+/// - `encoding`: how the input hits are encoded into the input signal
 pub fn load_train_data(
     name: &str,
     timestep: f64,
     input_width: usize,
     target_width: usize,
     shift: Option<usize>,
+    encoding: InputEncoding,
 ) -> Result<Data, Box<dyn std::error::Error>> {
+    Ok(read_train_data(name)?.to_timesteps(timestep, input_width, target_width, shift, encoding))
+}
+
+/// Read the training data from its `.bin` file, in the time-domain format
+pub fn read_train_data(name: &str) -> Result<TrainData, Box<dyn std::error::Error>> {
     let mut data_path = data_dir()?;
     data_path.push(format!("{}.bin", name));
 
@@ -259,89 +469,95 @@ pub fn load_train_data(
     let mut data = vec![];
     data_file.read_to_end(&mut data)?;
 
-    let mut train_data: TrainData = bincode::deserialize(data.as_slice())?;
-
-    let mut time_ms = 0.0;
-    let mut remaining_inputs = 0;
-
-    let mut inputs = vec![];
-    let mut targets = vec![];
-
-    while !train_data.targets.is_empty() && !train_data.inputs.is_empty() {
-        // this timestep's target
-        let mut target = None;
-        if train_data.targets[0].0 <= time_ms {
-            // this timestep is a target time
-            let target_val = match train_data.targets[0].1 {
-                true => TRAIN_DATA_HEIGHT,
-                false => 0.0,
-            };
-            target = Some(array![target_val]);
-            train_data.targets.pop_front();
-        }
+    TrainData::from_bytes(data.as_slice())
+}
 
-        if train_data.inputs[0].0 <= time_ms {
-            train_data.inputs.pop_front();
-            remaining_inputs = input_width;
-        }
+impl TrainData {
+    /// Convert the time-domain data into inputs and targets at every timestep, see
+    /// [`load_train_data`] for the arguments
+    pub fn to_timesteps(
+        mut self,
+        timestep: f64,
+        input_width: usize,
+        target_width: usize,
+        shift: Option<usize>,
+        encoding: InputEncoding,
+    ) -> Data {
+        let mut time_ms = 0.0;
+        let mut encoder = InputEncoder::new(encoding, input_width);
+
+        let mut inputs = vec![];
+        let mut targets = vec![];
+
+        while !self.targets.is_empty() && !self.inputs.is_empty() {
+            // this timestep's target
+            let mut target = None;
+            if self.targets[0].0 <= time_ms {
+                // this timestep is a target time, with one value per target channel
+                let target_vals = self.targets[0].1.iter().map(|onset| match onset {
+                    true => TRAIN_DATA_HEIGHT,
+                    false => 0.0,
+                });
+                target = Some(Array1::from_iter(target_vals));
+                self.targets.pop_front();
+            }
 
-        let mut input_val = 0.0;
+            if self.inputs[0].0 <= time_ms {
+                let (_, velocity) = self.inputs.pop_front().unwrap();
+                encoder.hit(velocity);
+            }
 
-        if remaining_inputs > 0 {
-            input_val = TRAIN_DATA_HEIGHT;
-            remaining_inputs -= 1;
-        }
+            let input = array![TRAIN_DATA_HEIGHT * encoder.step()];
 
-        let input = array![input_val];
+            inputs.push(input);
+            targets.push(target);
 
-        inputs.push(input);
-        targets.push(target);
+            time_ms += timestep;
+        }
 
-        time_ms += timestep;
-    }
+        // move the first targets
+        if let Some(shift) = shift {
+            let mut new_targets = vec![];
+            targets
+                .iter()
+                .skip(shift)
+                .for_each(|x| new_targets.push(x.clone()));
+
+            // add None for the shifted targets
+            for _ in 0..shift {
+                new_targets.push(None);
+            }
 
-    // move the first targets
-    if let Some(shift) = shift {
-        let mut new_targets = vec![];
-        targets
-            .iter()
-            .skip(shift)
-            .for_each(|x| new_targets.push(x.clone()));
-
-        // add None for the shifted targets
-        for _ in 0..shift {
-            new_targets.push(None);
-        }
+            // we expect these to be equal length
+            assert_eq!(targets.len(), new_targets.len());
 
-        // we expect these to be equal length
-        assert_eq!(targets.len(), new_targets.len());
+            log::info!("Shifted targets by {} timesteps", shift);
 
-        println!("Shifted targets by {} timesteps", shift);
+            targets = new_targets;
+        }
 
-        targets = new_targets;
-    }
+        // we expect the inputs and targets to be the same length
+        assert_eq!(inputs.len(), targets.len());
+
+        // post-process the targets to ensure the target width value is enforced
+        if target_width > 1 {
+            let left = target_width / 2 - 1;
+            let right = target_width / 2;
+            let indices: Vec<usize> = targets
+                .iter()
+                .enumerate()
+                .filter_map(|(i, x)| if x.is_some() { Some(i) } else { None })
+                .collect();
+
+            indices.iter().for_each(|i| {
+                let l = (i - left).max(0);
+                let r = (i + right).min(targets.len() - 1);
+                for j in l..=r {
+                    targets[j] = targets[*i].clone();
+                }
+            });
+        }
 
-    // we expect the inputs and targets to be the same length
-    assert_eq!(inputs.len(), targets.len());
-
-    // post-process the targets to ensure the target width value is enforced
-    if target_width > 1 {
-        let left = target_width / 2 - 1;
-        let right = target_width / 2;
-        let indices: Vec<usize> = targets
-            .iter()
-            .enumerate()
-            .filter_map(|(i, x)| if x.is_some() { Some(i) } else { None })
-            .collect();
-
-        indices.iter().for_each(|i| {
-            let l = (i - left).max(0);
-            let r = (i + right).min(targets.len() - 1);
-            for j in l..=r {
-                targets[j] = targets[*i].clone();
-            }
-        });
+        (inputs, targets)
     }
-
-    Ok((inputs, targets))
 }
@@ -0,0 +1,108 @@
+/*!
+  This module defines how input hits are encoded into the reservoir input signal.
+
+  The same encoder is used when converting training data into timesteps, and when running a model
+  live, which guarantees that the model sees the same features in both cases.
+*/
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, ValueEnum, Default, Debug, PartialEq)]
+pub enum InputEncoding {
+    /// Rectangular pulse of height 1, lasting `width` timesteps
+    #[default]
+    Pulse,
+    /// Rectangular pulse with the hit's velocity as height
+    Velocity,
+    /// Impulse of the hit's velocity, decaying exponentially with time constant `width`
+    Decay,
+    /// Time since the last hit, rising from 0 to 1 over `width` timesteps
+    Ramp,
+}
+
+/// Stateful encoder which turns input hits into one input value per timestep
+pub struct InputEncoder {
+    encoding: InputEncoding,
+    /// the width of the encoding in timesteps
+    width: usize,
+    /// velocity (0 to 1) of the last hit
+    velocity: f64,
+    /// timesteps since the last hit (`None` if there was no hit yet)
+    since_hit: Option<usize>,
+}
+
+impl InputEncoder {
+    pub fn new(encoding: InputEncoding, width: usize) -> Self {
+        Self {
+            encoding,
+            width,
+            velocity: 0.0,
+            since_hit: None,
+        }
+    }
+
+    /// Register a hit with the given velocity (0 to 1)
+    pub fn hit(&mut self, velocity: f64) {
+        self.velocity = velocity;
+        self.since_hit = Some(0);
+    }
+
+    /// Get the input value for the current timestep, and advance one timestep
+    pub fn step(&mut self) -> f64 {
+        let value = match (self.encoding, self.since_hit) {
+            (InputEncoding::Ramp, None) => 1.0,
+            (_, None) => 0.0,
+            (InputEncoding::Pulse, Some(t)) => match t < self.width {
+                true => 1.0,
+                false => 0.0,
+            },
+            (InputEncoding::Velocity, Some(t)) => match t < self.width {
+                true => self.velocity,
+                false => 0.0,
+            },
+            (InputEncoding::Decay, Some(t)) => {
+                self.velocity * (-(t as f64) / self.width.max(1) as f64).exp()
+            }
+            (InputEncoding::Ramp, Some(t)) => (t as f64 / self.width.max(1) as f64).min(1.0),
+        };
+
+        self.since_hit = self.since_hit.map(|t| t.saturating_add(1));
+
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pulse_lasts_width_timesteps() {
+        let mut encoder = InputEncoder::new(InputEncoding::Pulse, 3);
+        assert_eq!(encoder.step(), 0.0);
+
+        encoder.hit(0.5);
+        let values: Vec<f64> = (0..5).map(|_| encoder.step()).collect();
+        assert_eq!(values, vec![1.0, 1.0, 1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn decay_starts_at_velocity() {
+        let mut encoder = InputEncoder::new(InputEncoding::Decay, 10);
+        encoder.hit(0.8);
+
+        assert_eq!(encoder.step(), 0.8);
+        assert!(encoder.step() < 0.8);
+    }
+
+    #[test]
+    fn ramp_restarts_on_hit() {
+        let mut encoder = InputEncoder::new(InputEncoding::Ramp, 2);
+        assert_eq!(encoder.step(), 1.0);
+
+        encoder.hit(1.0);
+        let values: Vec<f64> = (0..4).map(|_| encoder.step()).collect();
+        assert_eq!(values, vec![0.0, 0.5, 1.0, 1.0]);
+    }
+}
@@ -15,6 +15,34 @@ pub enum NeuronError {
     FileNotFound(String),
     DataNotFound(String),
     ModelNotFound(String),
+    /// A file would be overwritten, without permission to do so
+    AlreadyExists(String),
+}
+
+/// Exit code for errors without a more specific code
+pub const EXIT_FAILURE: u8 = 1;
+/// Exit code when a file, dataset or model doesn't exist
+pub const EXIT_NOT_FOUND: u8 = 3;
+/// Exit code when a file would be overwritten (use `--force`)
+pub const EXIT_ALREADY_EXISTS: u8 = 4;
+
+/// The exit code of the program for an error, so that scripts can tell failures apart
+pub fn exit_code(error: &(dyn Error + 'static)) -> u8 {
+    if let Some(error) = error.downcast_ref::<NeuronError>() {
+        return match error {
+            NeuronError::FileNotFound(_)
+            | NeuronError::DataNotFound(_)
+            | NeuronError::ModelNotFound(_) => EXIT_NOT_FOUND,
+            NeuronError::AlreadyExists(_) => EXIT_ALREADY_EXISTS,
+            _ => EXIT_FAILURE,
+        };
+    }
+
+    match error.downcast_ref::<std::io::Error>() {
+        Some(error) if error.kind() == std::io::ErrorKind::NotFound => EXIT_NOT_FOUND,
+        Some(error) if error.kind() == std::io::ErrorKind::AlreadyExists => EXIT_ALREADY_EXISTS,
+        _ => EXIT_FAILURE,
+    }
 }
 
 impl Display for NeuronError {
@@ -32,6 +60,9 @@ impl Display for NeuronError {
             NeuronError::FileNotFound(s) => write!(f, "File `{}` not found.", s),
             NeuronError::DataNotFound(d) => write!(f, "Data `{}` does not extist.", d),
             NeuronError::ModelNotFound(m) => write!(f, "Model `{}` does not extist.", m),
+            NeuronError::AlreadyExists(s) => {
+                write!(f, "`{}` already exists, use --force to overwrite it.", s)
+            }
         }
     }
 }
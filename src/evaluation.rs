@@ -0,0 +1,292 @@
+/*!
+* Rhythm-aware evaluation of trained models.
+*
+* The squared error of the output doesn't tell whether a model plays the right notes at the right
+* time. Here the output is turned into onsets (by thresholding or peak picking), which are matched
+* to the target onsets within a tolerance window. From the matches, the onset precision, recall
+* and F1 score are computed, as well as the mean timing offset and the jitter (standard deviation
+* of the offsets).
+*/
+
+use std::fmt::Display;
+
+use clap::ValueEnum;
+use ndarray::Array1;
+use serde::{Deserialize, Serialize};
+
+use crate::{commands::TrainArgs, data::Data, reservoir::LinearReadout};
+
+/// How onsets are detected in the model output
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub enum OnsetDetection {
+    /// An onset where the output rises above the threshold (as `combine` does)
+    #[default]
+    Threshold,
+    /// An onset at the maximum of every part of the output above the threshold
+    Peak,
+}
+
+impl Display for OnsetDetection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OnsetDetection::Threshold => write!(f, "threshold"),
+            OnsetDetection::Peak => write!(f, "peak"),
+        }
+    }
+}
+
+/// The onset metrics of a model on the test split, over all outputs
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OnsetMetrics {
+    /// fraction of the detected onsets that match a target onset
+    pub precision: f64,
+    /// fraction of the target onsets that are detected
+    pub recall: f64,
+    pub f1: f64,
+    /// mean offset of the matched onsets (ms), positive means too late
+    pub mean_offset: f64,
+    /// standard deviation of the offsets of the matched onsets (ms)
+    pub jitter: f64,
+    /// amount of target onsets
+    pub targets: usize,
+    /// amount of detected onsets
+    pub detected: usize,
+    /// amount of detected onsets that match a target onset
+    pub matched: usize,
+}
+
+impl Display for OnsetMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "F1 {:.3} (precision {:.3}, recall {:.3}), offset {:.1} ms, jitter {:.1} ms",
+            self.f1, self.precision, self.recall, self.mean_offset, self.jitter
+        )
+    }
+}
+
+/// The timesteps of the onsets in a signal
+pub fn detect_onsets(signal: &[f64], detection: OnsetDetection, threshold: f64) -> Vec<usize> {
+    let mut onsets = vec![];
+    // the start and peak of the current part above the threshold
+    let mut above: Option<(usize, usize)> = None;
+
+    for (t, x) in signal.iter().enumerate() {
+        above = match (above, *x > threshold) {
+            (None, true) => Some((t, t)),
+            (Some((start, peak)), true) if *x > signal[peak] => Some((start, t)),
+            (Some(part), true) => Some(part),
+            (Some(part), false) => {
+                onsets.push(pick(part, detection));
+                None
+            }
+            (None, false) => None,
+        };
+    }
+    if let Some(part) = above {
+        onsets.push(pick(part, detection));
+    }
+
+    onsets
+}
+
+fn pick((start, peak): (usize, usize), detection: OnsetDetection) -> usize {
+    match detection {
+        OnsetDetection::Threshold => start,
+        OnsetDetection::Peak => peak,
+    }
+}
+
+/// The target onsets: the centers of the (widened) target pulses
+fn target_onsets(targets: &[Option<Array1<f64>>], channel: usize) -> Vec<usize> {
+    let signal: Vec<f64> = targets
+        .iter()
+        .map(|t| t.as_ref().map_or(0.0, |t| t[channel]))
+        .collect();
+
+    let mut onsets = vec![];
+    let mut start = None;
+    for t in 0..=signal.len() {
+        match (start, signal.get(t).is_some_and(|x| *x > 0.0)) {
+            (None, true) => start = Some(t),
+            (Some(s), false) => {
+                onsets.push((s + t - 1) / 2);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    onsets
+}
+
+/// Match the detected onsets to the target onsets (both sorted), returning the offsets (in
+/// timesteps) of the matches. Every onset is matched at most once, to the closest candidate.
+pub fn match_onsets(targets: &[usize], detected: &[usize], tolerance: usize) -> Vec<i64> {
+    let mut offsets = vec![];
+    let mut next = 0;
+
+    for &target in targets {
+        // skip the detected onsets that are too early for this target
+        while next < detected.len() && detected[next] + tolerance < target {
+            next += 1;
+        }
+
+        // the closest of the candidates within the window
+        let closest = detected[next..]
+            .iter()
+            .enumerate()
+            .take_while(|(_, d)| **d <= target + tolerance)
+            .min_by_key(|(_, d)| d.abs_diff(target));
+
+        if let Some((i, d)) = closest {
+            offsets.push(*d as i64 - target as i64);
+            next += i + 1;
+        }
+    }
+
+    offsets
+}
+
+/// Metrics of the outputs (one array per timestep) against the targets.
+///
+/// `timestep` is in ms, the `tolerance` (ms) is the maximum offset of a matching onset.
+pub fn onset_metrics(
+    outputs: &[Array1<f64>],
+    targets: &[Option<Array1<f64>>],
+    detection: OnsetDetection,
+    threshold: f64,
+    tolerance: f64,
+    timestep: f64,
+) -> OnsetMetrics {
+    let channels = outputs.first().map_or(0, |o| o.len());
+    let tolerance = (tolerance / timestep).round() as usize;
+
+    let mut offsets = vec![];
+    let (mut target_count, mut detected_count) = (0, 0);
+    for channel in 0..channels {
+        let signal: Vec<f64> = outputs.iter().map(|o| o[channel]).collect();
+        let detected = detect_onsets(&signal, detection, threshold);
+        let expected = target_onsets(targets, channel);
+
+        offsets.extend(match_onsets(&expected, &detected, tolerance));
+        target_count += expected.len();
+        detected_count += detected.len();
+    }
+
+    let matched = offsets.len();
+    let ratio = |a: usize, b: usize| if b == 0 { 0.0 } else { a as f64 / b as f64 };
+    let (precision, recall) = (ratio(matched, detected_count), ratio(matched, target_count));
+    let f1 = if precision + recall == 0.0 {
+        0.0
+    } else {
+        2.0 * precision * recall / (precision + recall)
+    };
+
+    let offsets: Vec<f64> = offsets.iter().map(|o| *o as f64 * timestep).collect();
+    let mean_offset = offsets.iter().sum::<f64>() / matched.max(1) as f64;
+    let jitter = (offsets
+        .iter()
+        .map(|o| (o - mean_offset).powi(2))
+        .sum::<f64>()
+        / matched.max(1) as f64)
+        .sqrt();
+
+    OnsetMetrics {
+        precision,
+        recall,
+        f1,
+        mean_offset,
+        jitter,
+        targets: target_count,
+        detected: detected_count,
+        matched,
+    }
+}
+
+/// Run the model on the inputs (from its initial state), and compute the onset metrics.
+///
+/// The washout of the model is not evaluated.
+pub fn evaluate(
+    nw: &mut dyn LinearReadout,
+    inputs: &[Array1<f64>],
+    targets: &[Option<Array1<f64>>],
+    args: &TrainArgs,
+) -> OnsetMetrics {
+    nw.reset_state();
+    let outputs: Vec<Array1<f64>> = inputs
+        .iter()
+        .map(|input| {
+            nw.forward(input);
+            nw.output().clone()
+        })
+        .collect();
+    nw.reset_state();
+
+    let washout = nw.washout().min(outputs.len());
+    onset_metrics(
+        &outputs[washout..],
+        &targets[washout..],
+        args.onset_detection,
+        args.onset_threshold,
+        args.onset_tolerance,
+        args.timestep,
+    )
+}
+
+/// Evaluate the model on the test split of its (loaded) training data
+pub fn evaluate_test_split(
+    nw: &mut dyn LinearReadout,
+    data: &Data,
+    args: &TrainArgs,
+) -> OnsetMetrics {
+    let (inputs, targets) = data;
+    let train_len = (inputs.len() as f64 * args.split) as usize;
+    evaluate(nw, &inputs[train_len..], &targets[train_len..], args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn onsets_are_detected_once_per_pulse() {
+        let signal = [0.0, 0.6, 0.9, 0.7, 0.1, 0.0, 0.8, 0.2, 0.6];
+
+        assert_eq!(
+            detect_onsets(&signal, OnsetDetection::Threshold, 0.5),
+            vec![1, 6, 8]
+        );
+        assert_eq!(
+            detect_onsets(&signal, OnsetDetection::Peak, 0.5),
+            vec![2, 6, 8]
+        );
+    }
+
+    #[test]
+    fn onsets_match_within_tolerance() {
+        // 13 is too late for 10, and 30 is only matched once
+        let offsets = match_onsets(&[10, 20, 30], &[13, 19, 29, 31], 2);
+        assert_eq!(offsets, vec![-1, -1]);
+    }
+
+    #[test]
+    fn metrics_of_a_late_model() {
+        let mut targets = vec![None; 20];
+        targets[5] = Some(Array1::ones(1));
+        targets[15] = Some(Array1::ones(1));
+        let outputs: Vec<Array1<f64>> = (0..20)
+            .map(|t| Array1::from_elem(1, if t == 6 || t == 10 { 1.0 } else { 0.0 }))
+            .collect();
+
+        let metrics = onset_metrics(&outputs, &targets, OnsetDetection::Threshold, 0.5, 4.0, 2.0);
+
+        assert_eq!(
+            (metrics.targets, metrics.detected, metrics.matched),
+            (2, 2, 1)
+        );
+        assert_eq!(metrics.f1, 0.5);
+        assert_eq!(metrics.mean_offset, 2.0);
+        assert_eq!(metrics.jitter, 0.0);
+    }
+}
@@ -1,6 +1,9 @@
+use make_csv::{csv_entry, csv_start};
+
 use crate::{
-    commands::{HyperArgs, TrainArgs},
-    reservoir::Reservoir,
+    commands::{onset_threshold, onset_tolerance, HyperArgs, TrainArgs},
+    evaluation::{evaluate_test_split, OnsetMetrics},
+    reservoir::{LinearReadout, Reservoir},
     trainutil::create_progress_bar,
 };
 
@@ -18,6 +21,18 @@ struct HyperparameterSpace {
     rho: RhoSpace,
     /// Regularization parameter
     lambda: LambdaSpace,
+    /// Input scaling
+    #[serde(default = "ScalingSpace::unit")]
+    input_scaling: ScalingSpace,
+    /// Bias scaling
+    #[serde(default = "ScalingSpace::zero")]
+    bias_scaling: ScalingSpace,
+    /// Output feedback scaling, only used with `feedback = true`
+    #[serde(default = "ScalingSpace::unit")]
+    feedback_scaling: ScalingSpace,
+    /// Whether the models use output feedback
+    #[serde(default)]
+    feedback: bool,
 }
 
 struct HyperparameterSet {
@@ -25,10 +40,14 @@ struct HyperparameterSet {
     alpha: f64,
     rho: f64,
     lambda: f64,
+    input_scaling: f64,
+    bias_scaling: f64,
+    feedback_scaling: f64,
+    feedback: bool,
 }
 
 impl HyperparameterSet {
-    fn to_args(&self) -> TrainArgs {
+    fn to_args(&self, seed: u64) -> TrainArgs {
         TrainArgs {
             size: self.n_neurons,
             iter: 100,
@@ -45,12 +64,47 @@ impl HyperparameterSet {
             dont_stop_early: false,
             data: "3_8".into(),
             list_data: false,
+            augment_tempo: 0.0,
+            augment_jitter: 0.0,
+            augment_dropout: 0.0,
+            augment_extra_hits: 0.0,
+            augment_shift: 0.0,
+            save_as: None,
+            force: false,
+            report: None,
             split: 0.9,
             grid: false,
             npy: None,
             shift: None,
             mode: crate::commands::TrainMode::Inv,
             activation: crate::activation::Activation::Tanh,
+            encoding: crate::encoding::InputEncoding::Pulse,
+            forgetting: 1.0,
+            topology: crate::commands::Topology::Random,
+            jump: 4,
+            neighbors: 4,
+            rewire: 0.1,
+            feedback: self.feedback,
+            input_scaling: self.input_scaling,
+            bias_scaling: self.bias_scaling,
+            feedback_scaling: self.feedback_scaling,
+            extra_data: vec![],
+            washout: 50,
+            model: crate::commands::ModelKind::Esn,
+            delays: 4,
+            stride: 10,
+            order: 2,
+            layers: 1,
+            layer_leak_rates: vec![],
+            layer_spectral_radii: vec![],
+            seed: Some(seed),
+            folds: None,
+            cv_lambdas: vec![],
+            cross_validation: None,
+            onset_detection: crate::evaluation::OnsetDetection::Threshold,
+            onset_threshold: onset_threshold(),
+            onset_tolerance: onset_tolerance(),
+            evaluation: None,
         }
     }
 }
@@ -89,9 +143,42 @@ struct LambdaSpace {
     count: usize,
 }
 
+/// Input, bias or feedback scaling
+#[derive(Debug, serde::Deserialize)]
+struct ScalingSpace {
+    min: f64,
+    max: f64,
+    count: usize,
+}
+
+impl ScalingSpace {
+    /// Only the given scaling, for spaces that aren't in the parameter file
+    fn single(scaling: f64) -> Self {
+        ScalingSpace {
+            min: scaling,
+            max: scaling,
+            count: 1,
+        }
+    }
+
+    fn unit() -> Self {
+        Self::single(1.0)
+    }
+
+    fn zero() -> Self {
+        Self::single(0.0)
+    }
+}
+
 impl HyperparameterSpace {
     fn len(&self) -> usize {
-        self.n_neurons.count * self.alpha.count * self.rho.count * self.lambda.count
+        self.n_neurons.count
+            * self.alpha.count
+            * self.rho.count
+            * self.lambda.count
+            * self.input_scaling.count
+            * self.bias_scaling.count
+            * self.feedback_scaling.count
     }
 }
 
@@ -146,6 +233,23 @@ impl Iterator for HyperparameterIter {
                 % self.0.lambda.count,
         );
 
+        let mut stride =
+            self.0.n_neurons.count * self.0.alpha.count * self.0.rho.count * self.0.lambda.count;
+        let index = self.1;
+        let mut scaling = |space: &ScalingSpace| {
+            let value = space_index_to_value(
+                space.min,
+                space.max,
+                space.count,
+                (index / stride) % space.count,
+            );
+            stride *= space.count;
+            value
+        };
+        let input_scaling = scaling(&self.0.input_scaling);
+        let bias_scaling = scaling(&self.0.bias_scaling);
+        let feedback_scaling = scaling(&self.0.feedback_scaling);
+
         self.1 += 1;
 
         Some(HyperparameterSet {
@@ -153,6 +257,10 @@ impl Iterator for HyperparameterIter {
             alpha,
             rho,
             lambda,
+            input_scaling,
+            bias_scaling,
+            feedback_scaling,
+            feedback: self.0.feedback,
         })
     }
 }
@@ -160,31 +268,53 @@ impl Iterator for HyperparameterIter {
 fn test_hypers(
     hypers: &HyperparameterSet,
     count: usize,
-) -> Result<(f64, Box<Reservoir>), Box<dyn std::error::Error>> {
-    let args = hypers.to_args();
+    seed: u64,
+) -> Result<(f64, OnsetMetrics, Box<Reservoir>), Box<dyn std::error::Error>> {
+    let args = hypers.to_args(seed);
 
     let mut nw = Reservoir::from_args(&args);
-    let error = nw.train(&args)?;
+    let (error, data) = nw.train(&args)?;
+    let metrics = evaluate_test_split(&mut nw, &data, &args);
     nw.plot(
         &args,
         format!(
-            "data/hypers_n{}_a{:.3}_r{:.3}_l{:.3}_count{}.svg",
-            hypers.n_neurons, hypers.alpha, hypers.rho, hypers.lambda, count
+            "data/hypers_n{}_a{:.3}_r{:.3}_l{:.3}_i{:.3}_b{:.3}_f{:.3}_count{}.svg",
+            hypers.n_neurons,
+            hypers.alpha,
+            hypers.rho,
+            hypers.lambda,
+            hypers.input_scaling,
+            hypers.bias_scaling,
+            hypers.feedback_scaling,
+            count
         )
         .as_str(),
     )?;
-    Ok((error, Box::new(nw)))
+    Ok((error, metrics, Box::new(nw)))
 }
 
 pub fn hyper(args: HyperArgs) -> Result<(), Box<dyn std::error::Error>> {
     let hyperfile = std::fs::read_to_string(args.path)?;
     let hyper: HyperparameterSpace = toml::from_str(&hyperfile)?;
 
+    // every trained model gets its own seed, derived from the base seed
+    let base_seed = args.seed.unwrap_or_else(rand::random);
+    println!("Using base seed \x1b[1m{}\x1b[0m", base_seed);
+
     let pb = create_progress_bar("Testing Hypers...", (hyper.len() * COUNT) as u64);
 
-    for hypers in hyper.into_iter() {
+    let mut wtr = csv_start!("data/hypers.csv");
+    csv_entry!(wtr <- "n", "alpha", "rho", "lambda", "input_scaling", "bias_scaling",
+        "feedback_scaling", "seed", "error", "f1", "precision", "recall", "offset", "jitter");
+
+    for (i, hypers) in hyper.into_iter().enumerate() {
         for c in 0..COUNT {
-            let (_error, _nw) = test_hypers(&hypers, c)?;
+            let seed = base_seed.wrapping_add((i * COUNT + c) as u64);
+            let (error, metrics, _nw) = test_hypers(&hypers, c, seed)?;
+            csv_entry!(wtr <- hypers.n_neurons, hypers.alpha, hypers.rho, hypers.lambda,
+                hypers.input_scaling, hypers.bias_scaling, hypers.feedback_scaling, seed, error,
+                metrics.f1, metrics.precision, metrics.recall, metrics.mean_offset,
+                metrics.jitter);
             pb.inc(1);
         }
     }
@@ -16,6 +16,7 @@
 * - [`combine`]: Combine the MIDI input, the model output, and the metronome output to create the
 * desired output.
 * - [`metronome`] : Currently not part of this crate, but a separate one.
+* - [`record`]: Record the messages of a live session, to turn them into training data.
 *
 * [`midi-broker`]: commands/fn.broke.html
 * [`combine`]: commands/fn.combine.html
@@ -24,6 +25,7 @@
 * [`run`]: commands/fn.run.html
 * [`train`]: commands/fn.train.html
 * [`metronome`]: commands/fn.metronome.html
+* [`record`]: commands/fn.record.html
 *
 * # Compilation
 * Clone this repository, then run:
@@ -47,7 +49,7 @@
 * ```sh
 * robodrummer train --data my_data
 * ```
-* This will train a random reservoir of 300 neurons, and plot the data if Python with `matplotlib` and `pandas` is installed.
+* This will train a random reservoir of 300 neurons, and plot the results to SVG files in `data/`.
 * The binary will prompt you to give the trained model a name.
 * <br>
 * To run the trained model, you can run:
@@ -59,21 +61,30 @@
 */
 
 pub mod activation;
+pub mod analyze;
 pub mod arpeggio;
+pub mod augmentation;
 pub mod commands;
 pub mod constants;
 pub mod data;
+pub mod encoding;
 pub mod errors;
+pub mod evaluation;
 pub mod guier;
 pub mod hyper;
 pub mod messages;
 pub mod metronomer;
 pub mod midier;
+pub mod midifile;
 pub mod midiutils;
+pub mod model;
+pub mod nvar;
 pub mod oscutil;
+pub mod plot;
 pub mod reservoir;
 pub mod robot;
 pub mod series;
+pub mod session;
 pub mod test_robot;
 pub mod trainutil;
 pub mod tui;
@@ -1,6 +1,8 @@
 extern crate blas_src;
 extern crate openblas_src;
 
+use std::process::ExitCode;
+
 use clap::Parser;
 
 use robodrummer::commands::broke;
@@ -8,18 +10,21 @@ use robodrummer::commands::combine;
 use robodrummer::commands::dev;
 use robodrummer::commands::gendata;
 use robodrummer::commands::metronome;
+use robodrummer::commands::models;
+use robodrummer::commands::record;
 use robodrummer::commands::run;
 use robodrummer::commands::train;
 use robodrummer::commands::tui;
 use robodrummer::commands::update_completions;
 use robodrummer::commands::Arguments;
+use robodrummer::errors::exit_code;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> ExitCode {
     // initialize the logging system
     env_logger::init();
 
     let args = Arguments::parse();
-    match args.command {
+    let result = match args.command {
         robodrummer::commands::Command::Train(t) => train(t),
         robodrummer::commands::Command::Run(r) => run(r, None),
         robodrummer::commands::Command::GenerateData(g) => gendata(g),
@@ -29,5 +34,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         robodrummer::commands::Command::Tui(t) => tui(t),
         robodrummer::commands::Command::Dev(d) => dev(d),
         robodrummer::commands::Command::Metronome(m) => metronome(m, None),
+        robodrummer::commands::Command::Models(m) => models(m),
+        robodrummer::commands::Command::Record(r) => record(r),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(exit_code(e.as_ref()))
+        }
     }
 }
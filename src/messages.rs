@@ -48,10 +48,30 @@ pub enum CombinerUpdate {
     Threshold(f32),
 }
 
+/// Requests to a running model, to freeze and recall its state
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ControlMessage {
+    /// Take a snapshot of the model state, and save it with the given name
+    Snapshot(String),
+    /// Continue from the snapshot with the given name
+    Recall(String),
+}
+
+/// The (decimated) state of a running model
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StateMessage {
+    /// the timestep of the state
+    pub step: u64,
+    /// evenly spaced values of the state
+    pub state: Vec<f32>,
+    /// the outputs of the model
+    pub outputs: Vec<f32>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum MidiNoteMessage {
-    /// The midi notes of the user input
-    InputNotes(Vec<u8>),
+    /// The midi notes of the user input, and the highest velocity among them
+    InputNotes(Vec<u8>, u8),
     /// A midi note at the output
     OutputNote,
 }
@@ -100,6 +120,14 @@ impl MidiNoteMessage {
     }
 
     pub fn is_input(&self) -> bool {
-        matches!(self, MidiNoteMessage::InputNotes(_))
+        matches!(self, MidiNoteMessage::InputNotes(..))
+    }
+
+    /// The velocity of an input message, scaled to the range 0 to 1
+    pub fn velocity(&self) -> Option<f64> {
+        match self {
+            MidiNoteMessage::InputNotes(_, velocity) => Some(*velocity as f64 / 127.0),
+            MidiNoteMessage::OutputNote => None,
+        }
     }
 }
@@ -1,9 +1,12 @@
 use std::{collections::VecDeque, time::Instant};
 
-use make_csv::{csv_entry, csv_start, python};
+use make_csv::{csv_entry, csv_start};
 use rustfft::{algorithm::Radix4, num_complex::Complex, Fft, FftDirection};
 
-use crate::metronomer::{frequency::FrequencyComponent, spectrum::Spectrum};
+use crate::{
+    metronomer::{frequency::FrequencyComponent, spectrum::Spectrum},
+    plot::plot_csv,
+};
 
 const MINIMUM_HITS_FOR_FOURIER: u32 = 5;
 const MINIMUM_FREQUENCY: f64 = 40.0 / 60.0;
@@ -257,8 +260,8 @@ impl InputWindow {
         frequencies.spectral_sum();
 
         {
-            let mut wtr = csv_start!("out.csv");
-            csv_entry!(wtr <- "t", "$\\text{Value}$");
+            let mut wtr = csv_start!("data/window.csv");
+            csv_entry!(wtr <- "t", "value");
 
             let Some(buffer) = self.create_fft_buffer() else {
                 panic!("No buffer found");
@@ -268,17 +271,21 @@ impl InputWindow {
                 csv_entry!(wtr <- i as f64 * self.sample_period, val.re);
             }
         }
-        let _ = python!("plot_freq.py");
+        if let Err(e) = plot_csv("data/window.csv", "data/window.svg") {
+            log::warn!("Could not plot the input window: {}", e);
+        }
 
         {
-            let mut wtr = csv_start!("out.csv");
-            csv_entry!(wtr <- "f", "$|F|$");
+            let mut wtr = csv_start!("data/spectrum.csv");
+            csv_entry!(wtr <- "f", "|F|");
             for freq in &frequencies.0 {
                 csv_entry!(wtr <- freq.0, freq.1);
             }
         }
 
-        let _ = python!("plot_freq.py");
+        if let Err(e) = plot_csv("data/spectrum.csv", "data/spectrum.svg") {
+            log::warn!("Could not plot the spectrum: {}", e);
+        }
     }
 }
 
@@ -0,0 +1,327 @@
+/*!
+* Reading Standard MIDI Files (e.g. recorded grooves) as training data.
+*
+* The notes of the file are split into an input stream and a target stream, by MIDI channel and
+* note number. The input and target selections can't overlap. Target onsets keep their recorded
+* timing, the absence of an onset is trained on a grid of beat subdivisions.
+*/
+
+use std::{collections::VecDeque, error::Error};
+
+use midly::{MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+
+use crate::commands::FromMidiArgs;
+
+/// Tempo of a MIDI file without tempo events (120 bpm) [µs per beat]
+const DEFAULT_TEMPO: f64 = 500_000.0;
+
+/// The input hits (time and velocity), and one target time series (time and onset) per channel
+pub type Streams = (VecDeque<(f64, f64)>, Vec<Vec<(f64, bool)>>);
+
+/// A note that starts in the MIDI file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Note {
+    /// onset time [ms]
+    pub time: f64,
+    /// the MIDI channel (1 to 16)
+    pub channel: u8,
+    pub key: u8,
+    /// the velocity (0 to 1)
+    pub velocity: f64,
+}
+
+/// Converts the ticks of a MIDI file to ms, following its tempo changes
+struct TempoMap {
+    /// tick, time [ms] and ms per tick from that tick onwards
+    segments: Vec<(u64, f64, f64)>,
+}
+
+impl TempoMap {
+    fn new(timing: Timing, mut tempos: Vec<(u64, f64)>) -> Self {
+        let ms_per_tick = |tempo: f64| match timing {
+            Timing::Metrical(ticks_per_beat) => tempo / 1000.0 / ticks_per_beat.as_int() as f64,
+            // timecode is independent of the tempo
+            Timing::Timecode(fps, subframes) => 1000.0 / (fps.as_f32() as f64 * subframes as f64),
+        };
+
+        tempos.sort_by_key(|(tick, _)| *tick);
+        let mut segments = vec![(0, 0.0, ms_per_tick(DEFAULT_TEMPO))];
+        for (tick, tempo) in tempos {
+            let time = Self::convert(&segments, tick);
+            segments.push((tick, time, ms_per_tick(tempo)));
+        }
+
+        TempoMap { segments }
+    }
+
+    fn convert(segments: &[(u64, f64, f64)], tick: u64) -> f64 {
+        let (start, time, ms_per_tick) = segments
+            .iter()
+            .rev()
+            .find(|(start, _, _)| *start <= tick)
+            .unwrap_or(&segments[0]);
+
+        time + (tick - start) as f64 * ms_per_tick
+    }
+
+    fn ms(&self, tick: u64) -> f64 {
+        Self::convert(&self.segments, tick)
+    }
+}
+
+impl FromMidiArgs {
+    fn is_input(&self, note: &Note) -> bool {
+        self.input_channel.is_none_or(|c| c == note.channel)
+            && (self.input_notes.is_empty() || self.input_notes.contains(&note.key))
+    }
+
+    /// Whether a note could be selected as input and as target at the same time, i.e. the
+    /// selections are neither on different channels nor of different notes
+    fn selections_overlap(&self) -> bool {
+        let channels_differ = matches!(
+            (self.input_channel, self.target_channel),
+            (Some(input), Some(target)) if input != target
+        );
+        let notes_differ = !self.input_notes.is_empty()
+            && !self.target_notes.is_empty()
+            && self
+                .input_notes
+                .iter()
+                .all(|n| !self.target_notes.contains(n));
+
+        !channels_differ && !notes_differ
+    }
+
+    /// The target channel (voice) of the note, if it is a target note
+    fn target_channel(&self, note: &Note) -> Option<usize> {
+        if self.target_channel.is_some_and(|c| c != note.channel) {
+            return None;
+        }
+
+        match (self.voices, self.target_notes.is_empty()) {
+            (true, _) => self.target_notes.iter().position(|k| *k == note.key),
+            (false, true) => Some(0),
+            (false, false) => self.target_notes.contains(&note.key).then_some(0),
+        }
+    }
+}
+
+/// All note onsets in a MIDI file, ordered by time, and the times of the grid of `resolution`
+/// subdivisions per beat. Files with timecode timing use `bpm` for the grid.
+pub fn read_notes(
+    bytes: &[u8],
+    resolution: u32,
+    bpm: f64,
+) -> Result<(Vec<Note>, Vec<f64>), Box<dyn Error>> {
+    let smf = Smf::parse(bytes)?;
+
+    let mut tempos = vec![];
+    let mut events = vec![];
+    for track in &smf.tracks {
+        let mut tick: u64 = 0;
+        for event in track {
+            tick += event.delta.as_int() as u64;
+            match event.kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => {
+                    tempos.push((tick, tempo.as_int() as f64))
+                }
+                // a note on with zero velocity is a note off
+                TrackEventKind::Midi {
+                    channel,
+                    message: MidiMessage::NoteOn { key, vel },
+                } if vel > 0 => events.push((tick, channel.as_int() + 1, key.as_int(), vel)),
+                _ => {}
+            }
+        }
+    }
+
+    let tempo_map = TempoMap::new(smf.header.timing, tempos);
+    let mut notes: Vec<Note> = events
+        .into_iter()
+        .map(|(tick, channel, key, vel)| Note {
+            time: tempo_map.ms(tick),
+            channel,
+            key,
+            velocity: vel.as_int() as f64 / 127.0,
+        })
+        .collect();
+    notes.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    let end = notes.last().map_or(0.0, |n| n.time);
+    let resolution = resolution.max(1);
+    let grid: Vec<f64> = match smf.header.timing {
+        Timing::Metrical(ticks_per_beat) => {
+            let step = ticks_per_beat.as_int() as f64 / resolution as f64;
+            (0..)
+                .map(|i| tempo_map.ms((i as f64 * step).round() as u64))
+                .take_while(|time| *time <= end)
+                .collect()
+        }
+        Timing::Timecode(..) => {
+            let step = 60_000.0 / bpm / resolution as f64;
+            (0..)
+                .map(|i| i as f64 * step)
+                .take_while(|time| *time <= end)
+                .collect()
+        }
+    };
+
+    Ok((notes, grid))
+}
+
+/// A target time series with the onsets, and no-onset instants on the grid points that are not
+/// within half a grid step of an onset
+pub fn onset_series(onsets: &[f64], grid: &[f64]) -> Vec<(f64, bool)> {
+    let mut series: Vec<(f64, bool)> = onsets.iter().map(|t| (*t, true)).collect();
+    series.extend(grid.iter().enumerate().filter_map(|(i, g)| {
+        // the grid may follow tempo changes, so the step is taken around every point
+        let step = match (grid.get(i + 1), i.checked_sub(1)) {
+            (Some(next), _) => next - g,
+            (None, Some(previous)) => g - grid[previous],
+            (None, None) => 0.0,
+        };
+        onsets
+            .iter()
+            .all(|t| (t - g).abs() > step / 2.0)
+            .then_some((*g, false))
+    }));
+    series.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    series
+}
+
+/// Split the notes of a MIDI file into the input hits (time and velocity), and one target time
+/// series per target channel (see [`onset_series`]).
+pub fn midi_to_streams(
+    bytes: &[u8],
+    args: &FromMidiArgs,
+    bpm: f64,
+) -> Result<Streams, Box<dyn Error>> {
+    if args.voices && args.target_notes.is_empty() {
+        return Err("Target notes are needed to split the targets into voices".into());
+    }
+    if args.selections_overlap() {
+        return Err(
+            "The input and target selections overlap, select different channels or notes".into(),
+        );
+    }
+
+    let (notes, grid) = read_notes(bytes, args.resolution, bpm)?;
+
+    let inputs: VecDeque<(f64, f64)> = notes
+        .iter()
+        .filter(|note| args.is_input(note))
+        .map(|note| (note.time, note.velocity))
+        .collect();
+
+    let channels = if args.voices {
+        args.target_notes.len()
+    } else {
+        1
+    };
+    let mut onsets: Vec<Vec<f64>> = vec![vec![]; channels];
+    for note in &notes {
+        if let Some(channel) = args.target_channel(note) {
+            onsets[channel].push(note.time);
+        }
+    }
+
+    if inputs.is_empty() || onsets.iter().all(|o| o.is_empty()) {
+        return Err(format!(
+            "The MIDI file has {} input and {} target notes, both are needed",
+            inputs.len(),
+            onsets.iter().map(|o| o.len()).sum::<usize>()
+        )
+        .into());
+    }
+
+    let targets = onsets
+        .iter()
+        .map(|onsets| onset_series(onsets, &grid))
+        .collect();
+
+    Ok((inputs, targets))
+}
+
+#[cfg(test)]
+mod tests {
+    use midly::{
+        num::{u15, u24, u28, u4, u7},
+        Format, Header, TrackEvent,
+    };
+
+    use super::*;
+
+    fn note(delta: u32, channel: u8, key: u8) -> TrackEvent<'static> {
+        TrackEvent {
+            delta: u28::new(delta),
+            kind: TrackEventKind::Midi {
+                channel: u4::new(channel),
+                message: MidiMessage::NoteOn {
+                    key: u7::new(key),
+                    vel: u7::new(127),
+                },
+            },
+        }
+    }
+
+    /// A beat of hi-hat input on channel 10, and a kick on the first beat at 60 bpm
+    fn groove() -> Vec<u8> {
+        let mut smf = Smf::new(Header::new(
+            Format::SingleTrack,
+            Timing::Metrical(u15::new(4)),
+        ));
+        smf.tracks.push(vec![
+            TrackEvent {
+                delta: u28::new(0),
+                kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::new(1_000_000))),
+            },
+            note(0, 9, 42),
+            note(0, 9, 36),
+            note(4, 9, 42),
+            note(4, 9, 42),
+            note(1, 9, 36),
+        ]);
+
+        let mut bytes = vec![];
+        smf.write_std(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn ticks_follow_the_tempo() {
+        let (notes, grid) = read_notes(&groove(), 2, 120.0).unwrap();
+
+        let times: Vec<f64> = notes.iter().map(|n| n.time).collect();
+        assert_eq!(times, vec![0.0, 0.0, 1000.0, 2000.0, 2250.0]);
+        assert_eq!(notes[0].channel, 10);
+        assert_eq!(grid, vec![0.0, 500.0, 1000.0, 1500.0, 2000.0]);
+    }
+
+    #[test]
+    fn notes_are_split_into_inputs_and_targets() {
+        // by default, every note would be both input and target
+        assert!(midi_to_streams(&groove(), &FromMidiArgs::default(), 120.0).is_err());
+
+        let args = FromMidiArgs {
+            input_notes: vec![42],
+            target_notes: vec![36],
+            resolution: 2,
+            ..Default::default()
+        };
+        let (inputs, targets) = midi_to_streams(&groove(), &args, 120.0).unwrap();
+
+        assert_eq!(inputs.len(), 3);
+        // the grid points at 0 and 2000 are too close to the kicks
+        assert_eq!(
+            targets,
+            vec![vec![
+                (0.0, true),
+                (500.0, false),
+                (1000.0, false),
+                (1500.0, false),
+                (2250.0, true)
+            ]]
+        );
+    }
+}
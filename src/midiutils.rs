@@ -1,11 +1,13 @@
 use std::{thread, time::Duration};
 
 use crate::midier::create_midi_output_and_connect;
-use make_csv::{csv_entry, csv_start, csv_stop, python};
+use make_csv::{csv_entry, csv_start, csv_stop};
 // use midi_control::MidiMessageSend;
 use ndarray::Array1;
 
-use crate::{add_data, reservoir::Reservoir, series::*, trainutil::add_series_data};
+use crate::{
+    add_data, plot::plot_csv, reservoir::Reservoir, series::*, trainutil::add_series_data,
+};
 
 pub fn send_beat(conn: &mut midir::MidiOutputConnection, num: u32) {
     let ch = match num {
@@ -72,5 +74,7 @@ pub fn play_model(mut model: Box<Reservoir>) {
     }
 
     csv_stop!(wtr);
-    python!("plot.py");
+    if let Err(e) = plot_csv("out.csv", "out.svg") {
+        log::warn!("Could not plot out.svg: {}", e);
+    }
 }
@@ -0,0 +1,423 @@
+/*!
+* Rhythm models that can be hosted by the live pipeline (`run`).
+*
+* Every kind of model implements [`RhythmModel`], and is stored in the models directory as a
+* [`StoredModel`], of which the variant is the type tag of the model. Loading a model dispatches
+* on that tag, so the live pipeline doesn't need to know which kind of model it is running.
+*
+* Model files start with a header with the format version, and embed the arguments the model was
+* trained with (see [`ModelFile`]). Files of older versions are migrated while loading.
+*/
+
+use std::{borrow::Cow, error::Error, fmt::Display, fs, io::Write, path::PathBuf};
+
+use ndarray::{Array1, ArrayView1};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    commands::TrainArgs,
+    data::models_dir,
+    nvar::Nvar,
+    reservoir::{DeepReservoir, LinearReadout, RealtimeReservoir, Reservoir},
+};
+
+/// A model which turns an input signal into rhythmic output signals, one timestep at a time.
+pub trait RhythmModel {
+    /// Apply one timestep with the given input
+    fn step(&mut self, input: &Array1<f64>);
+
+    /// The outputs of the last timestep
+    fn read_outputs(&self) -> ArrayView1<'_, f64>;
+
+    /// Reset the model to its initial state
+    fn reset(&mut self);
+
+    /// Information about the kind and shape of the model
+    fn metadata(&self) -> ModelMetadata;
+
+    /// A copy of the internal state and output of the model
+    fn snapshot(&self) -> Snapshot;
+
+    /// Continue from a snapshot, which has to be taken from a model of the same shape
+    fn restore(&mut self, snapshot: &Snapshot) -> Result<(), Box<dyn Error>>;
+
+    /// The trainable linear readout of the model, used for online adaptation.
+    ///
+    /// Models without a linear readout can't be adapted while running.
+    fn readout_mut(&mut self) -> Option<&mut dyn LinearReadout> {
+        None
+    }
+}
+
+/// Information about a loaded model
+#[derive(Debug, Clone)]
+pub struct ModelMetadata {
+    /// the kind of model
+    pub kind: &'static str,
+    /// number of inputs
+    pub inputs: usize,
+    /// number of outputs
+    pub outputs: usize,
+}
+
+impl Display for ModelMetadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} with {} inputs and {} outputs",
+            self.kind, self.inputs, self.outputs
+        )
+    }
+}
+
+/// The internal state and output of a model at some timestep.
+///
+/// Restoring a snapshot continues the model from that timestep, e.g. to recall a groove.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// the full internal state (e.g. all reservoir neurons, not only the visible ones)
+    pub state: Array1<f64>,
+    /// the output of the model
+    pub output: Array1<f64>,
+}
+
+impl Snapshot {
+    /// Check that the snapshot fits a model with the given state size and amount of outputs
+    pub fn check_shape(&self, state: usize, outputs: usize) -> Result<(), Box<dyn Error>> {
+        if self.state.len() != state || self.output.len() != outputs {
+            return Err(format!(
+                "The snapshot (state {}, {} outputs) doesn't fit the model (state {}, {} outputs)",
+                self.state.len(),
+                self.output.len(),
+                state,
+                outputs
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// At most `max_len` evenly spaced values of the state, e.g. to stream or plot it
+    pub fn decimated(&self, max_len: usize) -> Vec<f32> {
+        let stride = self.state.len().div_ceil(max_len.max(1)).max(1);
+        self.state
+            .iter()
+            .step_by(stride)
+            .map(|x| *x as f32)
+            .collect()
+    }
+
+    pub fn save_to_file(&self, filename: PathBuf) -> Result<(), Box<dyn Error>> {
+        fs::write(filename, bincode::serialize(self)?)?;
+        Ok(())
+    }
+
+    pub fn load_from_file(filename: PathBuf) -> Result<Self, Box<dyn Error>> {
+        Ok(bincode::deserialize(&fs::read(filename)?)?)
+    }
+}
+
+/// The magic bytes at the start of every model file
+const MAGIC: &[u8; 8] = b"RDMODEL\0";
+
+/// The version of the model file format that is written.
+///
+/// Older versions are migrated while loading:
+/// - 0: a bincode [`Reservoir`], without header
+/// - 1: never released
+/// - 2: [`MAGIC`], the version (u32, little endian), and a bincode [`ModelFile`]
+///
+/// Changing the serialized layout of any model requires a new version, and a migration from
+/// the previous one in [`ModelFile::migrate`].
+pub const FORMAT_VERSION: u32 = 2;
+
+/// A model as it is stored in the models directory.
+///
+/// The (bincode) variant index is the type tag of the stored file, so new kinds of models need to
+/// be added at the end.
+// only used while loading and saving, so the size difference between variants doesn't matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Serialize, Deserialize)]
+pub enum StoredModel<'a> {
+    Reservoir(Cow<'a, Reservoir>),
+    Deep(Cow<'a, DeepReservoir>),
+    Nvar(Cow<'a, Nvar>),
+}
+
+/// The contents of a model file (after the header)
+#[derive(Serialize, Deserialize)]
+pub struct ModelFile<'a> {
+    /// the arguments the model was trained with, as TOML, so that they can be read even if the
+    /// arguments changed since
+    pub metadata: Option<String>,
+    pub model: StoredModel<'a>,
+}
+
+impl StoredModel<'_> {
+    /// The path of the model file with the given name
+    pub fn path(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+        Ok(models_dir()?.join(name.to_string() + ".bin"))
+    }
+
+    pub fn save_to_file(
+        &self,
+        filename: PathBuf,
+        metadata: Option<&TrainArgs>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(filename)?;
+
+        let contents = ModelFile {
+            metadata: metadata.map(toml::to_string).transpose()?,
+            model: self.borrowed(),
+        };
+
+        file.write_all(MAGIC)?;
+        file.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        file.write_all(bincode::serialize(&contents)?.as_slice())?;
+
+        Ok(())
+    }
+
+    pub fn save(&self, name: &str, metadata: Option<&TrainArgs>) -> Result<(), Box<dyn Error>> {
+        self.save_to_file(Self::path(name)?, metadata)
+    }
+
+    fn borrowed(&self) -> StoredModel<'_> {
+        match self {
+            StoredModel::Reservoir(nw) => StoredModel::Reservoir(Cow::Borrowed(nw)),
+            StoredModel::Deep(nw) => StoredModel::Deep(Cow::Borrowed(nw)),
+            StoredModel::Nvar(nvar) => StoredModel::Nvar(Cow::Borrowed(nvar)),
+        }
+    }
+}
+
+impl ModelFile<'static> {
+    pub fn load_from_file(filename: PathBuf) -> Result<Self, Box<dyn Error>> {
+        let bytes = fs::read(&filename)?;
+
+        Self::from_bytes(&bytes)
+            .map_err(|e| format!("Can't load {}: {}", filename.display(), e).into())
+    }
+
+    pub fn load_from_name(name: &str) -> Result<Self, Box<dyn Error>> {
+        Self::load_from_file(StoredModel::path(name)?)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let Some(rest) = bytes.strip_prefix(MAGIC) else {
+            return Self::migrate_headerless(bytes);
+        };
+
+        let (version, contents) = match rest.split_first_chunk::<4>() {
+            Some((version, contents)) => (u32::from_le_bytes(*version), contents),
+            None => return Err("the model file is truncated".into()),
+        };
+
+        Self::migrate(version, contents)
+    }
+
+    /// Read the contents of a model file with the given format version
+    fn migrate(version: u32, contents: &[u8]) -> Result<Self, String> {
+        match version {
+            FORMAT_VERSION => bincode::deserialize(contents)
+                .map_err(|e| format!("the model file is corrupt ({})", e)),
+            v if v > FORMAT_VERSION => Err(format!(
+                "the model was saved with a newer version of this program \
+                 (format version {}, this version supports up to {})",
+                v, FORMAT_VERSION
+            )),
+            v => Err(format!("unknown model format version {}", v)),
+        }
+    }
+
+    /// Read the model files that were saved before the header (version 0)
+    fn migrate_headerless(bytes: &[u8]) -> Result<Self, String> {
+        let Ok(mut nw) = bincode::deserialize::<Reservoir>(bytes) else {
+            return Err("this is not a robodrummer model".into());
+        };
+        // these models were trained without output feedback
+        nw.disable_feedback();
+
+        Ok(ModelFile {
+            metadata: None,
+            model: StoredModel::Reservoir(Cow::Owned(nw)),
+        })
+    }
+
+    /// The arguments that the model was trained with, if they were stored
+    pub fn train_args(&self) -> Result<Option<TrainArgs>, Box<dyn Error>> {
+        Ok(self.metadata.as_deref().map(toml::from_str).transpose()?)
+    }
+}
+
+impl StoredModel<'static> {
+    pub fn load_from_file(filename: PathBuf) -> Result<Self, Box<dyn Error>> {
+        Ok(ModelFile::load_from_file(filename)?.model)
+    }
+
+    pub fn load_from_name(name: &str) -> Result<Self, Box<dyn Error>> {
+        Self::load_from_file(Self::path(name)?)
+    }
+
+    /// Turn the stored model into a model for the live pipeline
+    pub fn into_rhythm_model(self) -> Box<dyn RhythmModel> {
+        match self {
+            StoredModel::Reservoir(nw) => Box::new(nw.into_owned()),
+            StoredModel::Deep(nw) => Box::new(nw.into_owned()),
+            StoredModel::Nvar(nvar) => Box::new(nvar.into_owned()),
+        }
+    }
+}
+
+/// Load the model with the given name, whatever kind of model it is
+pub fn load_model(name: &str) -> Result<Box<dyn RhythmModel>, Box<dyn Error>> {
+    Ok(StoredModel::load_from_name(name)?.into_rhythm_model())
+}
+
+/// Load the model with the given name for inference only.
+///
+/// Reservoirs get an allocation-free forward path, optionally in single precision. Other kinds of
+/// models are loaded as they are.
+pub fn load_realtime_model(
+    name: &str,
+    single_precision: bool,
+) -> Result<Box<dyn RhythmModel>, Box<dyn Error>> {
+    Ok(match StoredModel::load_from_name(name)? {
+        StoredModel::Reservoir(nw) if single_precision => {
+            Box::new(RealtimeReservoir::<f32>::new(&nw))
+        }
+        StoredModel::Reservoir(nw) => Box::new(RealtimeReservoir::<f64>::new(&nw)),
+        model => model.into_rhythm_model(),
+    })
+}
+
+impl RhythmModel for Reservoir {
+    fn step(&mut self, input: &Array1<f64>) {
+        self.forward(input);
+    }
+
+    fn read_outputs(&self) -> ArrayView1<'_, f64> {
+        self.output.view()
+    }
+
+    fn reset(&mut self) {
+        self.reset_state();
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Reservoir::snapshot(self)
+    }
+
+    fn restore(&mut self, snapshot: &Snapshot) -> Result<(), Box<dyn Error>> {
+        Reservoir::restore(self, snapshot)
+    }
+
+    fn metadata(&self) -> ModelMetadata {
+        ModelMetadata {
+            kind: "ESN",
+            inputs: self.inputs,
+            outputs: self.output.len(),
+        }
+    }
+
+    fn readout_mut(&mut self) -> Option<&mut dyn LinearReadout> {
+        Some(self)
+    }
+}
+
+impl RhythmModel for DeepReservoir {
+    fn step(&mut self, input: &Array1<f64>) {
+        self.forward(input);
+    }
+
+    fn read_outputs(&self) -> ArrayView1<'_, f64> {
+        self.output.view()
+    }
+
+    fn reset(&mut self) {
+        self.reset_state();
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        DeepReservoir::snapshot(self)
+    }
+
+    fn restore(&mut self, snapshot: &Snapshot) -> Result<(), Box<dyn Error>> {
+        DeepReservoir::restore(self, snapshot)
+    }
+
+    fn metadata(&self) -> ModelMetadata {
+        ModelMetadata {
+            kind: "Deep ESN",
+            inputs: self.inputs,
+            outputs: self.output.len(),
+        }
+    }
+
+    fn readout_mut(&mut self) -> Option<&mut dyn LinearReadout> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reservoir() -> Reservoir {
+        Reservoir::new_builder()
+            .seed(2)
+            .from_size_input_outputs(20, 1, 1, 0.2)
+            .build()
+    }
+
+    #[test]
+    fn file_keeps_model_and_metadata() {
+        let args = TrainArgs {
+            size: 20,
+            leak_rate: 0.25,
+            ..Default::default()
+        };
+        let path = std::env::temp_dir().join(format!("model_file_{}.bin", std::process::id()));
+        StoredModel::Reservoir(Cow::Owned(reservoir()))
+            .save_to_file(path.clone(), Some(&args))
+            .unwrap();
+
+        let file = ModelFile::load_from_file(path.clone()).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert!(matches!(file.model, StoredModel::Reservoir(_)));
+        assert_eq!(file.train_args().unwrap().unwrap().leak_rate, 0.25);
+    }
+
+    #[test]
+    fn headerless_files_are_migrated() {
+        let legacy = bincode::serialize(&reservoir()).unwrap();
+        let file = ModelFile::from_bytes(&legacy).unwrap();
+        assert!(matches!(file.model, StoredModel::Reservoir(_)));
+        assert!(file.metadata.is_none());
+
+        // only the original reservoir files are migrated
+        let tagged = bincode::serialize(&StoredModel::Reservoir(Cow::Owned(reservoir()))).unwrap();
+        assert!(ModelFile::from_bytes(&tagged)
+            .err()
+            .unwrap()
+            .contains("not a robodrummer model"));
+    }
+
+    #[test]
+    fn incompatible_files_are_errors() {
+        let mut newer = MAGIC.to_vec();
+        newer.extend_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert!(ModelFile::from_bytes(&newer)
+            .err()
+            .unwrap()
+            .contains("newer version"));
+
+        assert!(ModelFile::from_bytes(b"not a model").is_err());
+        assert!(ModelFile::from_bytes(&MAGIC[..]).is_err());
+    }
+}
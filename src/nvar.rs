@@ -0,0 +1,327 @@
+/*!
+* Next generation reservoir computing: nonlinear vector autoregression (NVAR).
+*
+* Instead of a random recurrent reservoir, the features are built directly from the input:
+* - a constant
+* - time-delayed copies of the input (`delays` copies, `stride` timesteps apart)
+* - all unique monomials of those copies, up to the polynomial `order`
+*
+* A linear readout is trained on these features, so all training methods of
+* [`LinearReadout`] can be used.
+*/
+
+use std::{borrow::Cow, collections::VecDeque, error::Error, path::PathBuf};
+
+use ndarray::{s, Array1, Array2, ArrayView1};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    commands::TrainArgs,
+    model::{ModelMetadata, RhythmModel, Snapshot, StoredModel},
+    reservoir::LinearReadout,
+};
+
+/// A nonlinear vector autoregression model.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Nvar {
+    /// amount of time-delayed copies of the input
+    delays: usize,
+    /// amount of timesteps between the delayed copies
+    stride: usize,
+    /// the past inputs, the most recent one first
+    history: VecDeque<Array1<f64>>,
+    /// indices of the linear features that make up each nonlinear feature
+    monomials: Vec<Vec<usize>>,
+    /// the features of the current timestep: constant, linear, nonlinear
+    features: Array1<f64>,
+    /// output layer of the model
+    pub output: Array1<f64>,
+    /// output weights (features -> output)
+    pub weights_res_out: Array2<f64>,
+    /// number of inputs
+    pub inputs: usize,
+    /// number of outputs
+    outputs: usize,
+    /// learning rate of the model
+    learning_rate: f64,
+    /// regularization parameter lambda
+    regularization: f64,
+}
+
+/// All unique monomials of degree 2 up to `order` of `linear` variables, as lists of variable
+/// indices (combinations with replacement).
+fn monomials(linear: usize, order: usize) -> Vec<Vec<usize>> {
+    let mut all = Vec::new();
+    let mut current: Vec<Vec<usize>> = (0..linear).map(|i| vec![i]).collect();
+
+    for _ in 2..=order {
+        current = current
+            .iter()
+            .flat_map(|monomial| {
+                // only use variables from the last one onwards, to avoid duplicates
+                (*monomial.last().unwrap()..linear).map(move |i| {
+                    let mut monomial = monomial.clone();
+                    monomial.push(i);
+                    monomial
+                })
+            })
+            .collect();
+        all.extend(current.iter().cloned());
+    }
+
+    all
+}
+
+impl Nvar {
+    pub fn new(inputs: usize, outputs: usize, delays: usize, stride: usize, order: usize) -> Self {
+        let delays = delays.max(1);
+        let stride = stride.max(1);
+
+        let linear = inputs * delays;
+        let monomials = monomials(linear, order);
+        let feature_count = 1 + linear + monomials.len();
+
+        Nvar {
+            delays,
+            stride,
+            history: VecDeque::from(vec![Array1::zeros(inputs); (delays - 1) * stride + 1]),
+            monomials,
+            features: Array1::zeros(feature_count),
+            output: Array1::zeros(outputs),
+            weights_res_out: Array2::zeros((outputs, feature_count)),
+            inputs,
+            outputs,
+            learning_rate: 0.1,
+            regularization: 0.0,
+        }
+    }
+
+    pub fn from_args(args: &TrainArgs) -> Self {
+        let mut nvar = Nvar::new(
+            args.inputs,
+            args.outputs,
+            args.delays,
+            args.stride,
+            args.order,
+        );
+        nvar.learning_rate = args.learning_rate;
+        nvar.regularization = args.regularization;
+        nvar
+    }
+
+    /// The amount of features the readout is trained on
+    pub fn feature_count(&self) -> usize {
+        self.features.len()
+    }
+
+    pub fn forward(&mut self, input: &Array1<f64>) {
+        self.history.pop_back();
+        self.history.push_front(input.clone());
+
+        self.update_features();
+        self.output = self.weights_res_out.dot(&self.features);
+    }
+
+    /// Compute the features from the input history
+    fn update_features(&mut self) {
+        // constant
+        self.features[0] = 1.0;
+
+        // linear: the delayed copies of the input
+        for d in 0..self.delays {
+            let start = 1 + d * self.inputs;
+            self.features
+                .slice_mut(s![start..start + self.inputs])
+                .assign(&self.history[d * self.stride]);
+        }
+
+        // nonlinear: the monomials of the linear features
+        let offset = 1 + self.delays * self.inputs;
+        for (i, monomial) in self.monomials.iter().enumerate() {
+            let value: f64 = monomial.iter().map(|&j| self.features[1 + j]).product();
+            self.features[offset + i] = value;
+        }
+    }
+
+    pub fn reset_state(&mut self) {
+        self.history.iter_mut().for_each(|input| input.fill(0.0));
+        self.features = Array1::zeros(self.features.len());
+        self.output = Array1::zeros(self.outputs);
+    }
+
+    /// The snapshot holds the input history, the most recent input first
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            state: self.history.iter().flatten().copied().collect(),
+            output: self.output.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), Box<dyn Error>> {
+        snapshot.check_shape(self.history.len() * self.inputs, self.outputs)?;
+
+        for (input, past) in self
+            .history
+            .iter_mut()
+            .zip(snapshot.state.exact_chunks(self.inputs))
+        {
+            input.assign(&past);
+        }
+        self.update_features();
+        self.output.assign(&snapshot.output);
+
+        Ok(())
+    }
+
+    pub fn save_to_file(
+        &self,
+        filename: PathBuf,
+        metadata: Option<&TrainArgs>,
+    ) -> Result<(), Box<dyn Error>> {
+        StoredModel::Nvar(Cow::Borrowed(self)).save_to_file(filename, metadata)
+    }
+
+    pub fn load_from_name(model_name: &str) -> Result<Self, Box<dyn Error>> {
+        match StoredModel::load_from_name(model_name)? {
+            StoredModel::Nvar(nvar) => Ok(nvar.into_owned()),
+            _ => Err(format!("Model `{}` is not an NVAR model", model_name).into()),
+        }
+    }
+
+    pub fn save(&self, name: &str, metadata: Option<&TrainArgs>) -> Result<(), Box<dyn Error>> {
+        self.save_to_file(StoredModel::path(name)?, metadata)
+    }
+}
+
+impl LinearReadout for Nvar {
+    fn forward(&mut self, input: &Array1<f64>) {
+        Nvar::forward(self, input);
+    }
+
+    fn reset_state(&mut self) {
+        Nvar::reset_state(self);
+    }
+
+    fn readout_state(&self) -> ArrayView1<'_, f64> {
+        self.features.view()
+    }
+
+    fn output(&self) -> &Array1<f64> {
+        &self.output
+    }
+
+    fn readout_weights(&self) -> &Array2<f64> {
+        &self.weights_res_out
+    }
+
+    fn readout_weights_mut(&mut self) -> &mut Array2<f64> {
+        &mut self.weights_res_out
+    }
+
+    fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    fn outputs(&self) -> usize {
+        self.outputs
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+
+    fn regularization(&self) -> f64 {
+        self.regularization
+    }
+
+    fn set_regularization(&mut self, lambda: f64) {
+        self.regularization = lambda;
+    }
+
+    fn save(&self, name: &str, metadata: Option<&TrainArgs>) -> Result<(), Box<dyn Error>> {
+        Nvar::save(self, name, metadata)
+    }
+
+    fn boxed_clone(&self) -> Box<dyn LinearReadout + Send> {
+        Box::new(self.clone())
+    }
+
+    fn washout(&self) -> usize {
+        // the features are only complete once the history is filled
+        self.history.len() - 1
+    }
+}
+
+impl RhythmModel for Nvar {
+    fn step(&mut self, input: &Array1<f64>) {
+        self.forward(input);
+    }
+
+    fn read_outputs(&self) -> ArrayView1<'_, f64> {
+        self.output.view()
+    }
+
+    fn reset(&mut self) {
+        self.reset_state();
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Nvar::snapshot(self)
+    }
+
+    fn restore(&mut self, snapshot: &Snapshot) -> Result<(), Box<dyn Error>> {
+        Nvar::restore(self, snapshot)
+    }
+
+    fn metadata(&self) -> ModelMetadata {
+        ModelMetadata {
+            kind: "NVAR",
+            inputs: self.inputs,
+            outputs: self.outputs,
+        }
+    }
+
+    fn readout_mut(&mut self) -> Option<&mut dyn LinearReadout> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quadratic_monomials_are_unique() {
+        assert_eq!(monomials(2, 2), vec![vec![0, 0], vec![0, 1], vec![1, 1]]);
+        // 4 linear features: 10 quadratic and 20 cubic monomials
+        assert_eq!(monomials(4, 3).len(), 30);
+    }
+
+    #[test]
+    fn features_hold_delayed_inputs() {
+        let mut nvar = Nvar::new(1, 1, 2, 3, 2);
+        assert_eq!(nvar.feature_count(), 1 + 2 + 3);
+
+        nvar.forward(&Array1::from_elem(1, 2.0));
+        (0..3).for_each(|_| nvar.forward(&Array1::from_elem(1, 0.5)));
+
+        // the input from 3 steps ago is the second linear feature
+        assert_eq!(
+            nvar.readout_state().to_vec(),
+            vec![1.0, 0.5, 2.0, 0.25, 1.0, 4.0]
+        );
+    }
+
+    #[test]
+    fn harvest_discards_incomplete_history() {
+        let mut nvar = Nvar::new(1, 1, 2, 3, 2);
+        let inputs = vec![Array1::ones(1); 10];
+        let targets = vec![Some(Array1::ones(1)); 10];
+
+        let (states, target_outputs, _) = nvar.harvest_states(&inputs, &targets);
+
+        // the first 3 timesteps are the washout
+        assert_eq!(states.dim(), (6, 7));
+        assert_eq!(target_outputs.dim(), (1, 7));
+    }
+}
@@ -0,0 +1,197 @@
+/*!
+* Plots of the CSV files that are written while training and analysing models.
+*
+* The first column of a CSV file is the x axis. Columns named `target_*` are drawn as markers at
+* the rows where they have a value, all other columns as lines. The image format (SVG or PNG)
+* follows the extension of the output path.
+*
+* The text is rendered with an embedded font (DejaVu Sans), so no system fonts are needed.
+*/
+
+use std::{error::Error, path::Path, sync::OnceLock};
+
+use plotters::{coord::Shift, prelude::*};
+
+/// Size of the plots [px]
+const PLOT_SIZE: (u32, u32) = (1500, 700);
+
+/// The font of all text in the plots
+const FONT: &[u8] = include_bytes!("../assets/DejaVuSans.ttf");
+
+/// Register the embedded font as the default (sans-serif) font, once
+fn register_font() -> Result<(), Box<dyn Error>> {
+    static REGISTERED: OnceLock<Result<(), String>> = OnceLock::new();
+    REGISTERED
+        .get_or_init(|| {
+            plotters::style::register_font("sans-serif", FontStyle::Normal, FONT)
+                .map_err(|_| "The embedded font is invalid".to_string())
+        })
+        .clone()
+        .map_err(|e| e.into())
+}
+
+/// One named column of a plot
+#[derive(Debug, Clone, PartialEq)]
+pub struct Series {
+    pub name: String,
+    pub points: Vec<(f64, f64)>,
+    /// draw markers at the points, instead of a line through them
+    pub markers: bool,
+}
+
+/// Read the series of a CSV file, and the name of its x axis
+pub fn read_csv_series<P: AsRef<Path>>(path: P) -> Result<(String, Vec<Series>), Box<dyn Error>> {
+    let mut reader = csv::Reader::from_path(path.as_ref())?;
+    let headers = reader.headers()?.clone();
+    let Some(x_label) = headers.get(0) else {
+        return Err(format!("{} has no columns", path.as_ref().display()).into());
+    };
+
+    let mut series: Vec<Series> = headers
+        .iter()
+        .skip(1)
+        .map(|name| Series {
+            name: name.to_string(),
+            points: vec![],
+            markers: name.starts_with("target_"),
+        })
+        .collect();
+
+    for record in reader.records() {
+        let record = record?;
+        let Some(Ok(x)) = record.get(0).map(|x| x.trim().parse::<f64>()) else {
+            continue;
+        };
+        for (s, value) in series.iter_mut().zip(record.iter().skip(1)) {
+            // empty values (e.g. timesteps without a target) are not drawn
+            if let Ok(y) = value.trim().parse::<f64>() {
+                s.points.push((x, y));
+            }
+        }
+    }
+
+    for s in &mut series {
+        s.points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    }
+
+    Ok((x_label.to_string(), series))
+}
+
+/// Plot the series to an SVG or PNG file
+pub fn plot_series<P: AsRef<Path>>(
+    output: P,
+    x_label: &str,
+    series: &[Series],
+) -> Result<(), Box<dyn Error>> {
+    register_font()?;
+
+    let output = output.as_ref();
+    match output.extension().and_then(|e| e.to_str()) {
+        Some("svg") => draw(
+            SVGBackend::new(output, PLOT_SIZE).into_drawing_area(),
+            x_label,
+            series,
+        ),
+        Some("png") => draw(
+            BitMapBackend::new(output, PLOT_SIZE).into_drawing_area(),
+            x_label,
+            series,
+        ),
+        _ => Err(format!("Can't plot to {}, use .svg or .png", output.display()).into()),
+    }
+}
+
+/// Plot all columns of a CSV file to an SVG or PNG file
+pub fn plot_csv<P: AsRef<Path>, Q: AsRef<Path>>(csv: P, output: Q) -> Result<(), Box<dyn Error>> {
+    let (x_label, series) = read_csv_series(csv)?;
+    plot_series(output, &x_label, &series)
+}
+
+/// The range of the values, widened a bit so that nothing is drawn on the edge
+fn range(values: impl Iterator<Item = f64>, margin: f64) -> std::ops::Range<f64> {
+    let (min, max) = values
+        .filter(|v| v.is_finite())
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| {
+            (min.min(v), max.max(v))
+        });
+
+    match (min.is_finite(), max > min) {
+        (true, true) => {
+            let margin = (max - min) * margin;
+            (min - margin)..(max + margin)
+        }
+        // a single value
+        (true, false) => (min - 1.0)..(max + 1.0),
+        // no values at all
+        (false, _) => 0.0..1.0,
+    }
+}
+
+fn draw<DB: DrawingBackend>(
+    root: DrawingArea<DB, Shift>,
+    x_label: &str,
+    series: &[Series],
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    let points = || series.iter().flat_map(|s| s.points.iter());
+    let x_range = range(points().map(|p| p.0), 0.0);
+    let y_range = range(points().map(|p| p.1), 0.05);
+
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+        .margin(20)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(x_range, y_range)?;
+    chart.configure_mesh().x_desc(x_label).draw()?;
+
+    for (i, s) in series.iter().enumerate() {
+        let color = Palette99::pick(i).to_rgba();
+        if s.markers {
+            chart
+                .draw_series(s.points.iter().map(|p| Circle::new(*p, 2, color.filled())))?
+                .label(&s.name)
+                .legend(move |(x, y)| Circle::new((x, y), 3, color.filled()));
+        } else {
+            chart
+                .draw_series(LineSeries::new(
+                    s.points.iter().copied(),
+                    color.stroke_width(1),
+                ))?
+                .label(&s.name)
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
+        }
+    }
+
+    chart
+        .configure_series_labels()
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+    root.present()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_columns_become_series() {
+        let path = std::env::temp_dir().join("robodrummer_plot_test.csv");
+        std::fs::write(&path, "t,nw_0,target_0\n1,0.5,\n0,0.1,1\n2,0.2,0\n").unwrap();
+
+        let (x_label, series) = read_csv_series(&path).unwrap();
+
+        assert_eq!(x_label, "t");
+        assert_eq!(series.len(), 2);
+        assert_eq!(series[0].points, vec![(0.0, 0.1), (1.0, 0.5), (2.0, 0.2)]);
+        assert!(!series[0].markers);
+        // the target has no value at t = 1
+        assert_eq!(series[1].points, vec![(0.0, 1.0), (2.0, 0.0)]);
+        assert!(series[1].markers);
+    }
+}
@@ -0,0 +1,216 @@
+/*!
+* Diagnostics of the reservoir dynamics: memory capacity, Lyapunov exponent, eigenvalue spectrum
+* and readout weight statistics.
+*/
+
+use ndarray::{s, Array1, Array2, Axis};
+use ndarray_linalg::{c64, Eig};
+use ndarray_rand::RandomExt;
+use rand::{distributions::Uniform, rngs::StdRng, SeedableRng};
+use serde::Serialize;
+
+use super::{readout::ridge_regression, Reservoir};
+
+/// Regularization of the readouts that reconstruct the delayed inputs
+const MEMORY_REGULARIZATION: f64 = 1e-8;
+
+/// Size of the perturbation that is followed to estimate the Lyapunov exponent
+const PERTURBATION: f64 = 1e-8;
+
+/// Statistics of the readout weights of one output
+#[derive(Debug, Serialize)]
+pub struct ReadoutStatistics {
+    pub output: usize,
+    pub mean: f64,
+    pub std: f64,
+    pub min: f64,
+    pub max: f64,
+    /// the L2 norm of the weights
+    pub norm: f64,
+    /// the fraction of weights that are (practically) zero
+    pub zero_fraction: f64,
+}
+
+/// The linear memory capacity for delays `1..=max_delay`.
+///
+/// The reservoir (without output feedback) is driven by uniform random input, and for every delay
+/// `k` a linear readout is trained to reconstruct the input of `k` steps ago. The capacity of a
+/// delay is the squared correlation between the reconstruction and the delayed input on held-out
+/// steps, the total memory capacity is the sum over all delays.
+pub fn memory_capacity(
+    nw: &Reservoir,
+    max_delay: usize,
+    steps: usize,
+    seed: u64,
+) -> Result<Vec<f64>, String> {
+    let mut nw = nw.clone();
+    nw.disable_feedback();
+    nw.reset_state();
+
+    // the readouts are fitted on the first half of the states, and scored on the second half
+    let washout = nw.warm_up.max(max_delay);
+    let needed = washout + 2 * (nw.size + 1);
+    if steps < needed {
+        return Err(format!(
+            "At least {} steps are needed to estimate the memory capacity",
+            needed
+        ));
+    }
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let signal = Array1::random_using(steps, Uniform::new(-0.5, 0.5), &mut rng);
+
+    // the same signal goes to every input
+    let columns = steps - washout;
+    let mut states = Array2::zeros((nw.size + 1, columns));
+    for (t, u) in signal.iter().enumerate() {
+        nw.forward(&Array1::from_elem(nw.inputs, *u));
+
+        if t >= washout {
+            let mut column = states.column_mut(t - washout);
+            column.slice_mut(s![..nw.size]).assign(&nw.state);
+            // constant, so that the readouts have a bias
+            column[nw.size] = 1.0;
+        }
+    }
+
+    let targets = Array2::from_shape_fn((max_delay, columns), |(k, t)| signal[t + washout - k - 1]);
+    let (train_states, test_states) = states.view().split_at(Axis(1), columns / 2);
+    let (train_targets, test_targets) = targets.view().split_at(Axis(1), columns / 2);
+    let weights = ridge_regression(
+        &train_states.to_owned(),
+        &train_targets.to_owned(),
+        MEMORY_REGULARIZATION,
+    )?;
+    let reconstruction = weights.dot(&test_states);
+
+    Ok(test_targets
+        .axis_iter(Axis(0))
+        .zip(reconstruction.axis_iter(Axis(0)))
+        .map(|(target, output)| squared_correlation(&target.to_owned(), &output.to_owned()))
+        .collect())
+}
+
+fn squared_correlation(a: &Array1<f64>, b: &Array1<f64>) -> f64 {
+    let (a, b) = (a - a.mean().unwrap(), b - b.mean().unwrap());
+    let (var_a, var_b) = (a.dot(&a), b.dot(&b));
+    if var_a == 0.0 || var_b == 0.0 {
+        return 0.0;
+    }
+
+    a.dot(&b).powi(2) / (var_a * var_b)
+}
+
+/// Estimate the largest Lyapunov exponent (per timestep) while driven by `inputs`.
+///
+/// A copy of the reservoir is perturbed, and the distance between both trajectories is measured
+/// and renormalized every timestep (Benettin's method). The output feedback is part of the
+/// dynamics. A negative exponent means that the reservoir forgets perturbations (echo state
+/// property), a positive one that it is chaotic.
+pub fn lyapunov_exponent(nw: &Reservoir, inputs: &[Array1<f64>]) -> Result<f64, String> {
+    let mut nw = nw.clone();
+    nw.reset_state();
+
+    let washout = nw.warm_up.min(inputs.len());
+    if inputs.len() == washout {
+        return Err("There are no inputs left after the washout".into());
+    }
+    inputs[..washout].iter().for_each(|input| nw.forward(input));
+
+    // perturb all neurons equally
+    let mut perturbed = nw.clone();
+    perturbed.state += PERTURBATION / (nw.size as f64).sqrt();
+
+    let mut sum = 0.0;
+    for input in &inputs[washout..] {
+        nw.forward(input);
+        perturbed.forward(input);
+
+        let difference = &perturbed.state - &nw.state;
+        let distance = difference.dot(&difference).sqrt();
+        if distance == 0.0 {
+            // the perturbation vanished completely
+            return Ok(f64::NEG_INFINITY);
+        }
+        sum += (distance / PERTURBATION).ln();
+
+        // continue with a perturbation of the original size, in the same direction
+        let factor = PERTURBATION / distance;
+        perturbed.state = &nw.state + &(difference * factor);
+        perturbed.output = &nw.output + &((&perturbed.output - &nw.output) * factor);
+    }
+
+    Ok(sum / (inputs.len() - washout) as f64)
+}
+
+/// The eigenvalues of the resonant weights, ordered by decreasing magnitude
+pub fn eigenvalues(nw: &Reservoir) -> Result<Vec<c64>, String> {
+    let weights = match &nw.weights_rr_sparse {
+        Some(sparse) if nw.weights_res_res.is_empty() => sparse.to_dense(),
+        _ => nw.weights_res_res.clone(),
+    };
+
+    let (eig, _) = weights
+        .eig()
+        .map_err(|e| format!("Eigenvalue decomposition failed: {}", e))?;
+
+    let mut eig = eig.to_vec();
+    eig.sort_by(|a, b| b.norm().total_cmp(&a.norm()));
+    Ok(eig)
+}
+
+/// Statistics of the readout weights, per output
+pub fn readout_statistics(nw: &Reservoir) -> Vec<ReadoutStatistics> {
+    nw.weights_res_out
+        .axis_iter(Axis(0))
+        .enumerate()
+        .map(|(output, weights)| {
+            let n = weights.len().max(1) as f64;
+            ReadoutStatistics {
+                output,
+                mean: weights.mean().unwrap_or(0.0),
+                std: weights.std(0.0),
+                min: weights.fold(f64::INFINITY, |a, b| a.min(*b)),
+                max: weights.fold(f64::NEG_INFINITY, |a, b| a.max(*b)),
+                norm: weights.dot(&weights).sqrt(),
+                zero_fraction: weights.iter().filter(|w| w.abs() < 1e-9).count() as f64 / n,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reservoir(spectral_radius: f64) -> Reservoir {
+        let mut nw = Reservoir::new_builder()
+            .seed(8)
+            .from_size_input_outputs(50, 1, 1, 0.2)
+            .warm_up(20)
+            .build();
+        nw.scale(Some(spectral_radius));
+        nw
+    }
+
+    #[test]
+    fn memory_capacity_is_bounded() {
+        let capacity = memory_capacity(&reservoir(0.9), 20, 2000, 0).unwrap();
+
+        assert_eq!(capacity.len(), 20);
+        assert!(capacity.iter().all(|c| (0.0..=1.0 + 1e-9).contains(c)));
+        // the last input is remembered best
+        assert!(capacity[0] > 0.5);
+        assert!(capacity[0] > capacity[19]);
+    }
+
+    #[test]
+    fn contracting_reservoir_has_negative_exponent() {
+        let inputs: Vec<_> = (0..500)
+            .map(|t| Array1::from_elem(1, (t % 10) as f64 / 10.0))
+            .collect();
+
+        assert!(lyapunov_exponent(&reservoir(0.5), &inputs).unwrap() < 0.0);
+        assert!((eigenvalues(&reservoir(0.5)).unwrap()[0].norm() - 0.5).abs() < 1e-9);
+    }
+}
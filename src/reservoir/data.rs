@@ -1,17 +1,19 @@
 /*!
-* Data handling for the reservoir: saving and loading models
+* Data handling for the reservoir: saving and loading models, and exchanging them with Python
+* through npy files.
 */
 
-use std::fs;
-use std::io::Write;
-use std::{error::Error, path::PathBuf};
+use std::{borrow::Cow, error::Error, fs, path::Path, path::PathBuf};
 
-use serde::Deserialize;
+use ndarray::{Array, Dimension};
+use ndarray_npy::{ReadNpyExt, ReadableElement, WriteNpyExt};
+use serde::{Deserialize, Serialize};
 
 use super::Reservoir;
-use crate::data::models_dir;
+use crate::{activation::Activation, commands::TrainArgs, model::StoredModel};
 
-#[derive(Debug, Deserialize)]
+/// The metadata of a reservoir stored as npy files, see [`Reservoir::export_npy`].
+#[derive(Debug, Serialize, Deserialize)]
 pub struct NpyMetaData {
     pub leak_rate: f64,
     pub n: usize,
@@ -19,50 +21,120 @@ pub struct NpyMetaData {
     pub in_res_path: String,
     pub bias_path: String,
     pub out_path: String,
+    /// output feedback weights, models without them don't use feedback
+    #[serde(default)]
+    pub feedback_path: Option<String>,
+    /// models without an activation use the one of the builder
+    #[serde(default)]
+    pub activation: Option<Activation>,
 }
 
-impl Reservoir {
-    pub fn save_to_file(&self, filename: PathBuf) -> Result<(), Box<dyn Error>> {
-        let mut file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(filename)?;
+/// Read an npy file of either single or double precision.
+pub fn read_npy_f64<D>(path: &str) -> Result<Array<f64, D>, Box<dyn Error>>
+where
+    D: Dimension,
+{
+    fn read<T: ReadableElement, D: Dimension>(path: &str) -> Result<Array<T, D>, Box<dyn Error>> {
+        Ok(Array::<T, D>::read_npy(fs::File::open(path)?)?)
+    }
 
-        let model = bincode::serialize(self)?;
+    match read::<f64, D>(path) {
+        Ok(array) => Ok(array),
+        Err(_) => Ok(read::<f32, D>(path)?.mapv(|x| x as f64)),
+    }
+}
 
-        file.write_all(model.as_slice())?;
+/// Write an array to `dir/name`, returning the path as it is stored in the metadata.
+fn write_npy<A: WriteNpyExt>(dir: &Path, name: &str, array: &A) -> Result<String, Box<dyn Error>> {
+    let path = dir.join(name);
+    array.write_npy(fs::File::create(&path)?)?;
+    Ok(path.to_string_lossy().to_string())
+}
 
-        Ok(())
+impl Reservoir {
+    pub fn save_to_file(
+        &self,
+        filename: PathBuf,
+        metadata: Option<&TrainArgs>,
+    ) -> Result<(), Box<dyn Error>> {
+        StoredModel::Reservoir(Cow::Borrowed(self)).save_to_file(filename, metadata)
     }
 
     pub fn load_from_name(model_name: &str) -> Result<Self, Box<dyn Error>> {
-        let mut path = models_dir()?;
-
-        path.push(model_name.to_string() + ".bin");
-
-        let bytes = fs::read(path)?;
-
-        let model: Self = bincode::deserialize(bytes.as_slice())?;
-
-        Ok(model)
+        Self::load_from_file(StoredModel::path(model_name)?)
     }
 
     pub fn load_from_file(filename: PathBuf) -> Result<Self, Box<dyn Error>> {
-        let bytes = fs::read(filename)?;
-
-        let model: Self = bincode::deserialize(bytes.as_slice())?;
-
-        Ok(model)
+        match StoredModel::load_from_file(filename)? {
+            StoredModel::Reservoir(nw) => Ok(nw.into_owned()),
+            _ => Err("The stored model is not a (single) reservoir".into()),
+        }
     }
 
-    pub fn save(&self, name: &str) -> Result<(), Box<dyn Error>> {
-        let mut path = models_dir()?;
-
-        path.push(name.to_string() + ".bin");
+    pub fn save(&self, name: &str, metadata: Option<&TrainArgs>) -> Result<(), Box<dyn Error>> {
+        self.save_to_file(StoredModel::path(name)?, metadata)
+    }
 
-        self.save_to_file(path)?;
+    /// Export the weights as npy files (in double precision) to `dir`, together with a
+    /// `metadata.toml`, which can be loaded again with `--npy`.
+    pub fn export_npy(&self, dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+        fs::create_dir_all(dir)?;
+        let dir = fs::canonicalize(dir)?;
+
+        // large reservoirs might only have the sparse resonant weights
+        let res_res = match &self.weights_rr_sparse {
+            Some(sparse) if self.weights_res_res.is_empty() => sparse.to_dense(),
+            _ => self.weights_res_res.clone(),
+        };
+
+        let metadata = NpyMetaData {
+            leak_rate: self.leak_rate,
+            n: self.size,
+            res_res_path: write_npy(&dir, "res_res.npy", &res_res)?,
+            in_res_path: write_npy(&dir, "in_res.npy", &self.weights_in_res)?,
+            bias_path: write_npy(&dir, "bias.npy", &self.bias_res)?,
+            out_path: write_npy(&dir, "out.npy", &self.weights_res_out)?,
+            feedback_path: Some(write_npy(&dir, "feedback.npy", &self.weights_out_res)?),
+            activation: Some(self.activation.clone()),
+        };
+
+        let metadata_path = dir.join("metadata.toml");
+        fs::write(&metadata_path, toml::to_string(&metadata)?)?;
+
+        Ok(metadata_path)
+    }
+}
 
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use ndarray::{Array1, Array2};
+
+    use super::*;
+
+    #[test]
+    fn npy_export_round_trips() {
+        let mut nw = Reservoir::new_builder()
+            .seed(5)
+            .from_size_input_outputs(50, 2, 3, 0.2)
+            .activation(Activation::ReLu)
+            .leak_rate(0.4)
+            .build();
+        nw.scale(Some(0.9));
+        nw.weights_res_out = Array2::from_shape_fn(nw.weights_res_out.dim(), |(i, j)| {
+            ((i + j) % 5) as f64 / 10.0
+        });
+
+        let dir = std::env::temp_dir().join(format!("npy_export_{}", std::process::id()));
+        let metadata = nw.export_npy(&dir).unwrap();
+        let mut loaded = Reservoir::new_builder().from_npy(metadata).build();
+
+        for t in 0..30 {
+            let input = Array1::from_elem(2, (t % 4) as f64 / 4.0);
+            nw.forward(&input);
+            loaded.forward(&input);
+            assert_eq!(nw.output, loaded.output);
+        }
+
+        fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -0,0 +1,266 @@
+/*!
+* Deep (stacked) ESN: several reservoirs in a chain, with one readout over all of them.
+*
+* The first layer receives the input, every next layer receives the state of the previous layer.
+* The readout sees the concatenated states of all layers, so layers with different leak rates
+* and spectral radii can capture structure at different timescales (e.g. beats and bars).
+*/
+
+use std::{borrow::Cow, error::Error, path::PathBuf};
+
+use ndarray::{concatenate, s, Array1, Array2, ArrayView1, Axis};
+use serde::{Deserialize, Serialize};
+
+use super::{LinearReadout, Reservoir};
+use crate::{
+    commands::TrainArgs,
+    model::{Snapshot, StoredModel},
+};
+
+/// A stack of reservoirs, with a linear readout from the states of all layers.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DeepReservoir {
+    /// the reservoir layers, the first one receives the input
+    layers: Vec<Reservoir>,
+    /// the concatenated visible states of all layers
+    state: Array1<f64>,
+    /// output layer of the network
+    pub output: Array1<f64>,
+    /// output weights (all layers -> output)
+    pub weights_res_out: Array2<f64>,
+    /// number of inputs
+    pub inputs: usize,
+    /// number of outputs
+    outputs: usize,
+    /// learning rate of the network
+    learning_rate: f64,
+    /// regularization parameter lambda
+    regularization: f64,
+}
+
+impl DeepReservoir {
+    /// Create a deep reservoir with `args.layers` layers.
+    ///
+    /// Every layer is created like a regular reservoir from the arguments, where the leak rate
+    /// and spectral radius can be given per layer. Layers without their own value use the
+    /// global `leak_rate` and `spectral_radius`.
+    pub fn from_args(args: &TrainArgs) -> Self {
+        let mut layers: Vec<Reservoir> = Vec::with_capacity(args.layers);
+        let mut inputs = args.inputs;
+
+        for l in 0..args.layers.max(1) {
+            let layer_args = TrainArgs {
+                inputs,
+                // the layers themselves have no readout
+                outputs: 0,
+                leak_rate: *args.layer_leak_rates.get(l).unwrap_or(&args.leak_rate),
+                spectral_radius: *args
+                    .layer_spectral_radii
+                    .get(l)
+                    .unwrap_or(&args.spectral_radius),
+                // every layer gets different weights
+                seed: args.seed.map(|seed| seed.wrapping_add(l as u64)),
+                ..args.clone()
+            };
+
+            let layer = Reservoir::from_args(&layer_args);
+            inputs = layer.visible_count;
+            layers.push(layer);
+        }
+
+        let state_size = layers.iter().map(|layer| layer.visible_count).sum();
+
+        DeepReservoir {
+            layers,
+            state: Array1::zeros(state_size),
+            output: Array1::zeros(args.outputs),
+            weights_res_out: Array2::zeros((args.outputs, state_size)),
+            inputs: args.inputs,
+            outputs: args.outputs,
+            learning_rate: args.learning_rate,
+            regularization: args.regularization,
+        }
+    }
+
+    /// The amount of stacked reservoirs
+    pub fn depth(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn forward(&mut self, input: &Array1<f64>) {
+        let mut layer_input = input.clone();
+        let mut offset = 0;
+
+        for layer in self.layers.iter_mut() {
+            layer.forward(&layer_input);
+
+            let layer_state = layer.get_visible_state();
+            self.state
+                .slice_mut(s![offset..offset + layer_state.len()])
+                .assign(&layer_state);
+            offset += layer_state.len();
+
+            layer_input = layer_state.to_owned();
+        }
+
+        self.output = self.weights_res_out.dot(&self.state);
+    }
+
+    pub fn reset_state(&mut self) {
+        self.layers.iter_mut().for_each(|layer| layer.reset_state());
+        self.state = Array1::zeros(self.state.len());
+        self.output = Array1::zeros(self.outputs);
+    }
+
+    /// The snapshot holds the full states of all layers after each other
+    pub fn snapshot(&self) -> Snapshot {
+        let views: Vec<_> = self.layers.iter().map(|layer| layer.state.view()).collect();
+
+        Snapshot {
+            state: concatenate(Axis(0), &views).unwrap(),
+            output: self.output.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), Box<dyn Error>> {
+        let sizes: Vec<usize> = self.layers.iter().map(|layer| layer.state.len()).collect();
+        snapshot.check_shape(sizes.iter().sum(), self.output.len())?;
+
+        let (mut offset, mut visible_offset) = (0, 0);
+        for (layer, size) in self.layers.iter_mut().zip(sizes) {
+            layer.restore(&Snapshot {
+                state: snapshot.state.slice(s![offset..offset + size]).to_owned(),
+                // the layers have no readout
+                output: Array1::zeros(0),
+            })?;
+            offset += size;
+
+            let visible = layer.get_visible_state();
+            self.state
+                .slice_mut(s![visible_offset..visible_offset + visible.len()])
+                .assign(&visible);
+            visible_offset += visible.len();
+        }
+        self.output.assign(&snapshot.output);
+
+        Ok(())
+    }
+
+    pub fn get_output(&self, output_id: usize) -> f64 {
+        *self.output.get(output_id).unwrap()
+    }
+
+    pub fn save_to_file(
+        &self,
+        filename: PathBuf,
+        metadata: Option<&TrainArgs>,
+    ) -> Result<(), Box<dyn Error>> {
+        StoredModel::Deep(Cow::Borrowed(self)).save_to_file(filename, metadata)
+    }
+
+    pub fn load_from_name(model_name: &str) -> Result<Self, Box<dyn Error>> {
+        match StoredModel::load_from_name(model_name)? {
+            StoredModel::Deep(nw) => Ok(nw.into_owned()),
+            _ => Err(format!("Model `{}` is not a deep reservoir", model_name).into()),
+        }
+    }
+
+    pub fn save(&self, name: &str, metadata: Option<&TrainArgs>) -> Result<(), Box<dyn Error>> {
+        self.save_to_file(StoredModel::path(name)?, metadata)
+    }
+}
+
+impl LinearReadout for DeepReservoir {
+    fn forward(&mut self, input: &Array1<f64>) {
+        DeepReservoir::forward(self, input);
+    }
+
+    fn reset_state(&mut self) {
+        DeepReservoir::reset_state(self);
+    }
+
+    fn readout_state(&self) -> ArrayView1<'_, f64> {
+        self.state.view()
+    }
+
+    fn output(&self) -> &Array1<f64> {
+        &self.output
+    }
+
+    fn readout_weights(&self) -> &Array2<f64> {
+        &self.weights_res_out
+    }
+
+    fn readout_weights_mut(&mut self) -> &mut Array2<f64> {
+        &mut self.weights_res_out
+    }
+
+    fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    fn outputs(&self) -> usize {
+        self.outputs
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+
+    fn regularization(&self) -> f64 {
+        self.regularization
+    }
+
+    fn set_regularization(&mut self, lambda: f64) {
+        self.regularization = lambda;
+    }
+
+    fn save(&self, name: &str, metadata: Option<&TrainArgs>) -> Result<(), Box<dyn Error>> {
+        DeepReservoir::save(self, name, metadata)
+    }
+
+    fn boxed_clone(&self) -> Box<dyn LinearReadout + Send> {
+        Box::new(self.clone())
+    }
+
+    fn washout(&self) -> usize {
+        // all layers are created with the same washout
+        self.layers[0].warm_up
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readout_sees_all_layers() {
+        let args = TrainArgs {
+            size: 20,
+            inputs: 1,
+            outputs: 2,
+            layers: 3,
+            connectivity: 0.2,
+            spectral_radius: 0.9,
+            leak_rate: 0.5,
+            layer_leak_rates: vec![0.9, 0.1],
+            input_scaling: 1.0,
+            seed: Some(1),
+            ..Default::default()
+        };
+
+        let mut nw = DeepReservoir::from_args(&args);
+        assert_eq!(nw.depth(), 3);
+        assert_eq!(nw.readout_state().len(), 60);
+        assert_eq!(nw.readout_weights().dim(), (2, 60));
+
+        assert_eq!(nw.layers[0].leak_rate, 0.9);
+        assert_eq!(nw.layers[1].leak_rate, 0.1);
+        assert_eq!(nw.layers[2].leak_rate, 0.5);
+
+        nw.forward(&Array1::ones(1));
+        assert_eq!(nw.output.len(), 2);
+        // the input reaches the last layer within one step
+        assert!(nw.state.slice(s![40..]).iter().any(|x| *x != 0.0));
+    }
+}
@@ -5,26 +5,34 @@
 
 use std::{fmt::Display, fs, path::PathBuf, time::Instant};
 
-use make_csv::{csv_entry, csv_start, python};
-use ndarray::{s, Array, Array1, Array2, ArrayView1, Axis, Dimension, Ix2};
-use ndarray_linalg::{Eig, Inverse, SVD};
-use ndarray_npy::ReadNpyExt;
+use make_csv::{csv_entry, csv_start};
+use ndarray::{s, Array, Array1, Array2, ArrayView1, Dimension, Ix2};
+use ndarray_linalg::Eig;
 use ndarray_rand::{rand_distr::StandardNormal, RandomExt};
-use rand::{distributions::Uniform, rngs::ThreadRng, Rng};
+use rand::{distributions::Uniform, rngs::StdRng, Rng, SeedableRng};
 use rand_distr::num_traits::Zero;
+use rand_distr::Binomial;
 use serde::{Deserialize, Serialize};
 use sprs::prod::mul_acc_mat_vec_csr;
 
 use crate::{
     activation::Activation,
-    commands::{TrainArgs, TrainMode},
+    commands::{Topology, TrainArgs},
     constants,
     data::load_train_data,
+    model::Snapshot,
+    plot::plot_csv,
 };
 
 use self::data::NpyMetaData;
 
+pub use self::{deep::DeepReservoir, readout::LinearReadout, realtime::RealtimeReservoir};
+
+pub mod analysis;
 pub mod data;
+pub mod deep;
+pub mod readout;
+pub mod realtime;
 
 /// A ESN (Echo State Network) reservoir.
 #[derive(Clone, Serialize, Deserialize)]
@@ -62,14 +70,20 @@ pub struct Reservoir {
     pub leak_rate: f64,
     /// learning rate of the network
     learning_rate: f64,
-    /// number of warm-up steps (currently unused)
+    /// number of warm-up (washout) steps, of which the states are not used for training
     warm_up: usize,
     /// regularization parameter lambda
     regularization: f64,
 }
 
+/// Weight of the jump connections of a cycle reservoir with jumps, relative to the cycle weights
+const JUMP_WEIGHT: f64 = 0.5;
+
 /// A builder for the Reservoir struct.
-pub struct ReservoirBuilder(Reservoir);
+///
+/// All random weights are drawn from the builder's random number generator, which can be seeded
+/// to make the resulting reservoir reproducible.
+pub struct ReservoirBuilder(Reservoir, StdRng);
 
 impl Display for Reservoir {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -90,9 +104,10 @@ impl Display for Reservoir {
 }
 
 /// ensure that the ndarray has zero-entries for approx. `(1 - conn_fract)` fraction of entries.
-fn connectivity<D>(arr: &mut Array<f64, D>, conn_fract: f64, rng: &mut ThreadRng)
+fn connectivity<D, R>(arr: &mut Array<f64, D>, conn_fract: f64, rng: &mut R)
 where
     D: Dimension,
+    R: Rng,
 {
     arr.iter_mut().for_each(|x| {
         if !rng.gen_bool(conn_fract) {
@@ -109,39 +124,49 @@ where
     arr.iter_mut().for_each(|x| *x *= factor);
 }
 
-fn pseudo_inverse(matrix: &Array2<f64>, regularization: f64) -> Result<Array2<f64>, String> {
-    let start = std::time::Instant::now();
-    let Ok(svd_result) = matrix.svd(true, true) else {
-        return Err("SVD failed @ calculation".to_string());
-    };
-
-    let (Some(u), sigma, Some(vt)) = svd_result else {
-        return Err("SVD failed @ unpacking into U, Sigma, V".to_string());
-    };
-
-    let mut s = Array2::zeros((matrix.shape()[0], matrix.shape()[1]));
+/// Estimate the spectral radius of a (square) sparse matrix using power iteration.
+///
+/// The matrix is not symmetric, so the dominant eigenvalues can be complex and the iterate doesn't
+/// converge to one eigenvector. The growth of its norm still does (Gelfand's formula), so the
+/// geometric mean of the growth per step is used as the estimate, after a burn-in period.
+fn estimate_spectral_radius(matrix: &sprs::CsMat<f64>) -> f64 {
+    const BURN_IN: usize = 200;
+    const ITERATIONS: usize = 800;
+
+    let size = matrix.rows();
+
+    // fixed seed, so the scaling of a seeded reservoir is reproducible
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut x: Array1<f64> = Array::random_using(size, StandardNormal, &mut rng);
+    x /= x.dot(&x).sqrt();
+
+    let mut log_growth = 0.0;
+    for i in 0..BURN_IN + ITERATIONS {
+        let mut ax: Array1<f64> = Array1::zeros(size);
+        mul_acc_mat_vec_csr(matrix.view(), x.view(), ax.view_mut());
+
+        let norm = ax.dot(&ax).sqrt();
+        if norm == 0.0 {
+            // nilpotent matrix (e.g. a delay line)
+            return 0.0;
+        }
 
-    for (i, val) in sigma.iter().enumerate() {
-        // if the value is very small, we set it to zero (which may be weird?)
-        if *val < 1e-9 {
-            s[[i, i]] = 0.0;
-        } else {
-            s[[i, i]] = 1.0 / (val + regularization);
+        if i >= BURN_IN {
+            log_growth += norm.ln();
         }
+        x = ax / norm;
     }
 
-    let pseudo_inv = vt.t().dot(&s.t()).dot(&u.t());
-    log::debug!("Pseudo-inverse calculation: {:?}", start.elapsed());
-
-    Ok(pseudo_inv)
+    (log_growth / ITERATIONS as f64).exp()
 }
 
 /// For all non-zero entries of the array, set it to either `either`, or `or`.
 /// `either` is selected with a probability of `fract`.
 #[allow(unused)]
-fn either_or<T, D>(arr: &mut Array<T, D>, either: T, or: T, fract: f64, rng: &mut ThreadRng)
+fn either_or<T, D, R>(arr: &mut Array<T, D>, either: T, or: T, fract: f64, rng: &mut R)
 where
     D: Dimension,
+    R: Rng,
     T: std::cmp::PartialEq + Zero + Clone,
 {
     arr.iter_mut().for_each(|x| {
@@ -182,6 +207,14 @@ where
 }
 
 impl ReservoirBuilder {
+    /// Seed the random number generator of this builder.
+    ///
+    /// This needs to be called before the weights are generated.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.1 = StdRng::seed_from_u64(seed);
+        self
+    }
+
     pub fn from_size_input_outputs(
         mut self,
         size: usize,
@@ -197,16 +230,20 @@ impl ReservoirBuilder {
         let state = Array1::zeros(size);
         let output = Array1::zeros(outputs);
 
-        let mut weights_in_res: Array2<f64> = Array::random((size, inputs), StandardNormal);
-        let mut weights_res_res: Array2<f64> = Array::random((size, size), StandardNormal);
-        let mut weights_res_out: Array2<f64> = Array::random((outputs, size), StandardNormal);
-        let mut weights_out_res: Array2<f64> = Array::random((size, outputs), StandardNormal);
-
-        let mut rng = rand::thread_rng();
-        connectivity(&mut weights_res_res, conn, &mut rng);
-        connectivity(&mut weights_in_res, conn, &mut rng);
+        let rng = &mut self.1;
+        let mut weights_in_res: Array2<f64> =
+            Array::random_using((size, inputs), StandardNormal, rng);
+        let mut weights_res_res: Array2<f64> =
+            Array::random_using((size, size), StandardNormal, rng);
+        let mut weights_res_out: Array2<f64> =
+            Array::random_using((outputs, size), StandardNormal, rng);
+        let mut weights_out_res: Array2<f64> =
+            Array::random_using((size, outputs), StandardNormal, rng);
+
+        connectivity(&mut weights_res_res, conn, rng);
+        connectivity(&mut weights_in_res, conn, rng);
         // scale(&mut weights_in_res, 0.001);
-        connectivity(&mut weights_res_out, conn, &mut rng);
+        connectivity(&mut weights_res_out, conn, rng);
 
         if constants::OUTPUT_NEURON_DIRECT_FEEDBACK {
             // with direct feedback, the out -> res weights are the transpose of the res -> out weights
@@ -214,7 +251,7 @@ impl ReservoirBuilder {
             // efficiency
             weights_out_res = weights_res_out.t().to_owned();
         } else {
-            connectivity(&mut weights_out_res, conn, &mut rng);
+            connectivity(&mut weights_out_res, conn, rng);
         }
 
         // either_or(&mut weights_out_res, -0.1, 0.1, 0.5, &mut rng);
@@ -222,7 +259,7 @@ impl ReservoirBuilder {
         // let bias_res: Array1<f64> = Array::random((size,), StandardNormal);
         // let bias_res: Array1<f64> = Array::random((size,), Uniform::new(-0.01, 0.01));
         let bias_res: Array1<f64> = Array::zeros((size,));
-        let bias_out: Array1<f64> = Array::random((outputs,), Uniform::new(-0.01, 0.01));
+        let bias_out: Array1<f64> = Array::random_using((outputs,), Uniform::new(-0.01, 0.01), rng);
 
         self.0.state = state;
         self.0.output = output;
@@ -238,6 +275,88 @@ impl ReservoirBuilder {
         self
     }
 
+    /// Create a random reservoir directly in the sparse representation.
+    ///
+    /// The dense resonant weights are never allocated, which makes it possible to create
+    /// reservoirs of thousands of neurons. Every neuron gets `Binomial(size, conn)` incoming
+    /// connections from randomly chosen neurons.
+    pub fn from_size_input_outputs_sparse(
+        mut self,
+        size: usize,
+        inputs: usize,
+        outputs: usize,
+        conn: f64,
+    ) -> Self {
+        self = self.with_io_weights(size, inputs, outputs, conn);
+
+        let rng = &mut self.1;
+        // the resonant weights, one (CSR) row at a time
+        let connections = Binomial::new(size as u64, conn.clamp(0.0, 1.0)).unwrap();
+        let mut indptr = Vec::with_capacity(size + 1);
+        let mut indices = Vec::new();
+        let mut data = Vec::new();
+        indptr.push(0);
+        for _ in 0..size {
+            let count = rng.sample(connections) as usize;
+            let mut columns = rand::seq::index::sample(rng, size, count).into_vec();
+            columns.sort_unstable();
+
+            for column in columns {
+                indices.push(column);
+                data.push(rng.sample::<f64, _>(StandardNormal));
+            }
+            indptr.push(indices.len());
+        }
+
+        self.0.weights_rr_sparse = Some(sprs::CsMat::new((size, size), indptr, indices, data));
+
+        self
+    }
+
+    /// Set the size of the reservoir, and draw the input, output and feedback weights.
+    ///
+    /// The resonant weights are left empty, they are up to the caller (e.g. a topology).
+    fn with_io_weights(mut self, size: usize, inputs: usize, outputs: usize, conn: f64) -> Self {
+        self.0.size = size;
+        self.0.inputs = inputs;
+        self.0.outputs = outputs;
+        self.0.visible_count = size;
+
+        let rng = &mut self.1;
+        let mut weights_in_res: Array2<f64> =
+            Array::random_using((size, inputs), StandardNormal, rng);
+        let mut weights_res_out: Array2<f64> =
+            Array::random_using((outputs, size), StandardNormal, rng);
+
+        connectivity(&mut weights_in_res, conn, rng);
+        connectivity(&mut weights_res_out, conn, rng);
+
+        let weights_out_res = if constants::OUTPUT_NEURON_DIRECT_FEEDBACK {
+            weights_res_out.t().to_owned()
+        } else {
+            let mut weights_out_res: Array2<f64> =
+                Array::random_using((size, outputs), StandardNormal, rng);
+            connectivity(&mut weights_out_res, conn, rng);
+            weights_out_res
+        };
+
+        let bias_out: Array1<f64> = Array::random_using((outputs,), Uniform::new(-0.01, 0.01), rng);
+
+        self.0.state = Array1::zeros(size);
+        self.0.output = Array1::zeros(outputs);
+
+        self.0.weights_in_res = weights_in_res;
+        self.0.weights_res_res = Array2::zeros((0, 0));
+        self.0.weights_rr_sparse = None;
+        self.0.weights_out_res = weights_out_res;
+        self.0.weights_res_out = weights_res_out;
+
+        self.0.bias_res = Array1::zeros(size);
+        self.0.bias_out = bias_out;
+
+        self
+    }
+
     /// Create a reservoir with a grid structure.
     ///
     /// The size is the square root of the amount of neurons (the length of one side of the grid)
@@ -246,8 +365,7 @@ impl ReservoirBuilder {
         self = self.from_size_input_outputs(size * size, inputs, outputs, 1.0);
 
         // set the output connectivity
-        let mut rng = rand::thread_rng();
-        connectivity(&mut self.0.weights_res_out, 0.2, &mut rng);
+        connectivity(&mut self.0.weights_res_out, 0.2, &mut self.1);
 
         // set the input to the first row of the reservoir
         // mask_first_n_rows(&mut self.0.weights_in_res, size);
@@ -259,47 +377,183 @@ impl ReservoirBuilder {
         self
     }
 
+    /// Create a simple cycle reservoir: every neuron only feeds the next one, in one big ring.
+    ///
+    /// All ring connections have the same weight, so the spectral radius sets their value.
+    pub fn from_cycle(mut self, size: usize, inputs: usize, outputs: usize) -> Self {
+        self = self.with_io_weights(size, inputs, outputs, 1.0);
+
+        let mut weights_res_res = Array2::zeros((size, size));
+        for i in 0..size {
+            weights_res_res[[(i + 1) % size, i]] = 1.0;
+        }
+        self.0.weights_res_res = weights_res_res;
+
+        self
+    }
+
+    /// Create a cycle reservoir with regular jumps.
+    ///
+    /// On top of the ring of [`ReservoirBuilder::from_cycle`], every `jump`-th neuron has a
+    /// bidirectional connection with the neuron `jump` places further along the ring.
+    ///
+    /// # Panics
+    /// If `jump` is not at least 2 and smaller than `size`, as the jumps would then overwrite the
+    /// ring connections.
+    pub fn from_cycle_jumps(
+        mut self,
+        size: usize,
+        inputs: usize,
+        outputs: usize,
+        jump: usize,
+    ) -> Self {
+        assert!(
+            (2..size).contains(&jump),
+            "the jump distance should be in [2, {}), got {}",
+            size,
+            jump
+        );
+        self = self.from_cycle(size, inputs, outputs);
+
+        for i in (0..size).step_by(jump) {
+            let j = (i + jump) % size;
+            if i != j {
+                self.0.weights_res_res[[i, j]] = JUMP_WEIGHT;
+                self.0.weights_res_res[[j, i]] = JUMP_WEIGHT;
+            }
+        }
+
+        self
+    }
+
+    /// Create a delay line reservoir: a chain of neurons, each feeding only the next one.
+    ///
+    /// The resulting weights are nilpotent (spectral radius zero), thus they are not rescaled.
+    pub fn from_delay_line(mut self, size: usize, inputs: usize, outputs: usize) -> Self {
+        self = self.with_io_weights(size, inputs, outputs, 1.0);
+
+        let mut weights_res_res = Array2::zeros((size, size));
+        for i in 1..size {
+            weights_res_res[[i, i - 1]] = 1.0;
+        }
+        self.0.weights_res_res = weights_res_res;
+
+        self
+    }
+
+    /// Create a small-world (Watts–Strogatz) reservoir.
+    ///
+    /// Neurons start out in a ring lattice where each neuron is connected to its `neighbors`
+    /// nearest neighbors (half on each side). Every connection is then rewired to a random
+    /// neuron with probability `rewire`. Connection weights are drawn from a standard normal
+    /// distribution.
+    ///
+    /// # Panics
+    /// If `rewire` is not a probability in `[0, 1]`.
+    pub fn from_small_world(
+        mut self,
+        size: usize,
+        inputs: usize,
+        outputs: usize,
+        neighbors: usize,
+        rewire: f64,
+    ) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&rewire),
+            "the rewire probability should be in [0, 1], got {}",
+            rewire
+        );
+        self = self.with_io_weights(size, inputs, outputs, 1.0);
+
+        let mut adjacency: Array2<bool> = Array2::from_elem((size, size), false);
+        for i in 0..size {
+            for d in 1..=(neighbors / 2).min(size.saturating_sub(1) / 2) {
+                let j = (i + d) % size;
+                adjacency[[i, j]] = true;
+                adjacency[[j, i]] = true;
+            }
+        }
+
+        // rewire each lattice edge (i, i + d) with probability `rewire`
+        let rng = &mut self.1;
+        for i in 0..size {
+            for d in 1..=(neighbors / 2).min(size.saturating_sub(1) / 2) {
+                let j = (i + d) % size;
+                if !adjacency[[i, j]] || !rng.gen_bool(rewire) {
+                    continue;
+                }
+
+                let candidates: Vec<usize> = (0..size)
+                    .filter(|&k| k != i && !adjacency[[i, k]])
+                    .collect();
+                if candidates.is_empty() {
+                    continue;
+                }
+
+                let k = candidates[rng.gen_range(0..candidates.len())];
+                adjacency[[i, j]] = false;
+                adjacency[[j, i]] = false;
+                adjacency[[i, k]] = true;
+                adjacency[[k, i]] = true;
+            }
+        }
+
+        let weights: Array2<f64> = Array::random_using((size, size), StandardNormal, rng);
+        self.0.weights_res_res =
+            Array2::from_shape_fn((size, size), |(i, j)| match adjacency[[i, j]] {
+                true => weights[[i, j]],
+                false => 0.0,
+            });
+
+        self
+    }
+
+    /// Load a reservoir from npy files, e.g. a pre-trained pytorch model or an export of
+    /// `models export`.
+    ///
+    /// The amount of inputs, outputs and visible neurons follow from the shapes of the weights.
+    /// Weights can be stored in single or double precision.
     pub fn from_npy(mut self, metadatapath: PathBuf) -> Self {
         let metadata =
             toml::from_str::<NpyMetaData>(&fs::read_to_string(metadatapath).unwrap()).unwrap();
 
+        // initialize resonant weights
+        self.0.weights_res_res = data::read_npy_f64(&metadata.res_res_path).unwrap();
+        self.0.weights_rr_sparse = None;
+
+        // initialize input weights
+        self.0.weights_in_res = data::read_npy_f64(&metadata.in_res_path).unwrap();
+
+        // initialize reservoir bias weights
+        self.0.bias_res = data::read_npy_f64(&metadata.bias_path).unwrap();
+
+        // initialize output weights to the pre-trained values
+        self.0.weights_res_out = data::read_npy_f64(&metadata.out_path).unwrap();
+
         // initialize parameters
         self.0.size = metadata.n;
-        self.0.inputs = 1;
-        self.0.outputs = 1;
+        self.0.inputs = self.0.weights_in_res.ncols();
+        self.0.outputs = self.0.weights_res_out.nrows();
 
         // initialize state and output neurons
         self.0.state = Array1::zeros(self.0.size);
         self.0.output = Array1::zeros(self.0.outputs);
 
-        // initialize resonant weights
-        let res_res_reader = fs::File::open(metadata.res_res_path).unwrap();
-        let res = Array2::<f32>::read_npy(res_res_reader).unwrap();
-        self.0.weights_res_res = res.mapv(|x| x as f64);
-
-        // initialize input weights
-        let in_res_reader = fs::File::open(metadata.in_res_path).unwrap();
-        let res = Array2::<f32>::read_npy(in_res_reader).unwrap();
-        self.0.weights_in_res = res.mapv(|x| x as f64);
-
-        // initialize reservoir bias weights
-        let bias_reader = fs::File::open(metadata.bias_path).unwrap();
-        let bias = Array1::<f32>::read_npy(bias_reader).unwrap();
-        self.0.bias_res = bias.mapv(|x| x as f64);
-
-        // initialize feedback weights (no feedback, thus zero)
-        self.0.weights_out_res = Array2::zeros((self.0.size, self.0.outputs));
+        // initialize feedback weights (no feedback if not given, thus zero)
+        self.0.weights_out_res = match &metadata.feedback_path {
+            Some(path) => data::read_npy_f64(path).unwrap(),
+            None => Array2::zeros((self.0.size, self.0.outputs)),
+        };
 
         // do not use output bias
         self.0.bias_out = Array1::zeros(self.0.outputs);
 
-        // initialize output weights to the pre-trained values
-        let out_res_reader = fs::File::open(metadata.out_path).unwrap();
-        let res = Array2::<f32>::read_npy(out_res_reader).unwrap();
-        self.0.weights_res_out = res.mapv(|x| x as f64);
+        // only the neurons that the output weights see are visible
+        self.0.visible_count = self.0.weights_res_out.ncols();
 
-        // set visible parameter to ensure that o neurons won't be trained
-        self.0.visible_count = metadata.n / 3;
+        if let Some(activation) = metadata.activation {
+            self.0.activation = activation;
+        }
 
         self.leak_rate(metadata.leak_rate)
     }
@@ -324,6 +578,48 @@ impl ReservoirBuilder {
         self
     }
 
+    /// Enable or disable output feedback (output -> reservoir).
+    ///
+    /// Without feedback, the feedback weights are zero.
+    pub fn feedback(mut self, enabled: bool) -> Self {
+        if !enabled {
+            self.0.weights_out_res.fill(0.0);
+        }
+        self
+    }
+
+    /// Scale the input weights
+    pub fn input_scaling(mut self, scaling: f64) -> Self {
+        self.0.weights_in_res *= scaling;
+        self
+    }
+
+    /// Draw the reservoir bias uniformly from `[-scaling, scaling]`, a scaling of 0 means no bias
+    ///
+    /// The sign of the scaling doesn't matter, as the range is symmetric.
+    pub fn bias_scaling(mut self, scaling: f64) -> Self {
+        let scaling = scaling.abs();
+        self.0.bias_res = if scaling == 0.0 {
+            Array1::zeros(self.0.size)
+        } else {
+            let distribution = Uniform::new_inclusive(-scaling, scaling);
+            Array::random_using(self.0.size, distribution, &mut self.1)
+        };
+        self
+    }
+
+    /// Scale the output feedback weights
+    pub fn feedback_scaling(mut self, scaling: f64) -> Self {
+        self.0.weights_out_res *= scaling;
+        self
+    }
+
+    /// Set the amount of washout timesteps, of which the states are not used for training
+    pub fn warm_up(mut self, steps: usize) -> Self {
+        self.0.warm_up = steps;
+        self
+    }
+
     pub fn build(self) -> Reservoir {
         self.0
     }
@@ -331,41 +627,67 @@ impl ReservoirBuilder {
 
 impl Reservoir {
     pub fn new_builder() -> ReservoirBuilder {
-        ReservoirBuilder(Reservoir {
-            state: Array1::zeros(0),
-            output: Array1::zeros(0),
-            weights_in_res: Array2::zeros((0, 0)),
-            weights_res_res: Array2::zeros((0, 0)),
-            weights_out_res: Array2::zeros((0, 0)),
-            weights_res_out: Array2::zeros((0, 0)),
-            bias_res: Array1::zeros(0),
-            bias_out: Array1::zeros(0),
-            size: 0,
-            visible_count: 0,
-            inputs: 0,
-            outputs: 0,
-            activation: Activation::Linear,
-            leak_rate: 0.95,
-            learning_rate: 0.1,
-            warm_up: 50,
-            regularization: 0.0,
-            weights_rr_sparse: None,
-        })
+        ReservoirBuilder(
+            Reservoir {
+                state: Array1::zeros(0),
+                output: Array1::zeros(0),
+                weights_in_res: Array2::zeros((0, 0)),
+                weights_res_res: Array2::zeros((0, 0)),
+                weights_out_res: Array2::zeros((0, 0)),
+                weights_res_out: Array2::zeros((0, 0)),
+                bias_res: Array1::zeros(0),
+                bias_out: Array1::zeros(0),
+                size: 0,
+                visible_count: 0,
+                inputs: 0,
+                outputs: 0,
+                activation: Activation::Linear,
+                leak_rate: 0.95,
+                learning_rate: 0.1,
+                warm_up: 50,
+                regularization: 0.0,
+                weights_rr_sparse: None,
+            },
+            StdRng::from_entropy(),
+        )
     }
 
     pub fn from_args(args: &TrainArgs) -> Reservoir {
+        let builder = match args.seed {
+            Some(seed) => Reservoir::new_builder().seed(seed),
+            None => Reservoir::new_builder(),
+        };
+
+        let (size, inputs, outputs) = (args.size, args.inputs, args.outputs);
         let nw = if args.grid {
-            Reservoir::new_builder().from_grid(args.size, args.inputs, args.outputs)
+            builder.from_grid(size, inputs, outputs)
             // .leak_rate(0.001)
         } else if let Some(npy) = &args.npy {
-            Reservoir::new_builder().from_npy(npy.into())
+            builder.from_npy(npy.into())
         } else {
-            Reservoir::new_builder().from_size_input_outputs(
-                args.size,
-                args.inputs,
-                args.outputs,
-                args.connectivity,
-            )
+            match args.topology {
+                Topology::Random if size > constants::SPARSE_RESERVOIR_SIZE => {
+                    builder.from_size_input_outputs_sparse(size, inputs, outputs, args.connectivity)
+                }
+                Topology::Random => {
+                    builder.from_size_input_outputs(size, inputs, outputs, args.connectivity)
+                }
+                Topology::Cycle => builder.from_cycle(size, inputs, outputs),
+                Topology::CycleJumps => builder.from_cycle_jumps(size, inputs, outputs, args.jump),
+                Topology::DelayLine => builder.from_delay_line(size, inputs, outputs),
+                Topology::SmallWorld => {
+                    builder.from_small_world(size, inputs, outputs, args.neighbors, args.rewire)
+                }
+            }
+        };
+
+        // imported weights are used as they are
+        let nw = match args.npy {
+            Some(_) => nw,
+            None => nw
+                .input_scaling(args.input_scaling)
+                .bias_scaling(args.bias_scaling)
+                .feedback_scaling(args.feedback_scaling),
         };
 
         let mut nw = nw
@@ -373,6 +695,8 @@ impl Reservoir {
             .leak_rate(args.leak_rate)
             .regularization(args.regularization)
             .activation(args.activation.clone())
+            .feedback(args.feedback)
+            .warm_up(args.washout)
             .build();
 
         if args.npy.is_none() {
@@ -382,18 +706,41 @@ impl Reservoir {
         nw
     }
 
-    pub fn set_weights_out(&mut self, weights: Array2<f64>) {
-        self.weights_res_out = weights;
-    }
-
+    /// Scale the resonant weights to get the target spectral radius (1.0 by default).
+    ///
+    /// Small reservoirs use the exact (dense) eigenvalues, large or sparse-only reservoirs use a
+    /// power iteration estimate.
     pub fn scale(&mut self, target: Option<f64>) {
-        let (eig, _) = self.weights_res_res.eig().unwrap();
+        let max_eig = match &self.weights_rr_sparse {
+            Some(sparse) => estimate_spectral_radius(sparse),
+            None if self.size > constants::SPARSE_RESERVOIR_SIZE => estimate_spectral_radius(
+                &sprs::CsMat::csr_from_dense(self.weights_res_res.view(), 0.0),
+            ),
+            None => {
+                let (eig, _) = self.weights_res_res.eig().unwrap();
+
+                // the eigenvalues are not sorted
+                eig.iter().map(|e| e.norm()).fold(0.0, f64::max)
+            }
+        };
 
-        let max_eig = eig[0].norm();
+        // e.g. a delay line has no eigenvalues different from zero, and can't be scaled
+        if max_eig < 1e-12 {
+            log::warn!("Spectral radius is zero, not scaling the reservoir weights");
+            return;
+        }
 
         let target = target.unwrap_or(1.0);
 
         self.weights_res_res = &self.weights_res_res * target / (max_eig);
+        if let Some(sparse) = &mut self.weights_rr_sparse {
+            sparse.scale(target / max_eig);
+        }
+    }
+
+    /// Remove the output feedback, by setting the feedback weights to zero
+    pub fn disable_feedback(&mut self) {
+        self.weights_out_res.fill(0.0);
     }
 
     pub fn reset_state(&mut self) {
@@ -406,7 +753,14 @@ impl Reservoir {
     }
 
     /// forward the externally supplied state, and return the output array
-    pub fn forward_external(&self, state: &mut Array1<f64>, input: &Array1<f64>) -> Array1<f64> {
+    ///
+    /// `feedback` is the output of the previous timestep, which is fed back into the reservoir.
+    pub fn forward_external(
+        &self,
+        state: &mut Array1<f64>,
+        input: &Array1<f64>,
+        feedback: &Array1<f64>,
+    ) -> Array1<f64> {
         let mut new_state = match &self.weights_rr_sparse {
             Some(sparse) => {
                 let mut res = Array::<f64, _>::zeros(state.len());
@@ -417,6 +771,7 @@ impl Reservoir {
         };
 
         new_state = new_state + self.weights_in_res.dot(input);
+        new_state += &self.weights_out_res.dot(feedback);
         new_state += &self.bias_res;
         new_state.mapv_inplace(|x| self.activation.apply(x));
         *state *= 1.0 - self.leak_rate;
@@ -444,6 +799,8 @@ impl Reservoir {
         };
         let resres_time = start.elapsed();
         new_state = new_state + self.weights_in_res.dot(input);
+        // the previous output is fed back (or the target, when teacher forcing)
+        new_state += &self.weights_out_res.dot(&self.output);
         new_state += &self.bias_res;
         new_state.mapv_inplace(|x| self.activation.apply(x));
         self.state = (1.0 - self.leak_rate) * &self.state + self.leak_rate * &new_state;
@@ -457,167 +814,26 @@ impl Reservoir {
         log::trace!("ResRes: {:?}", resres_time);
     }
 
-    pub fn get_output(&self, output_id: usize) -> f64 {
-        *self.output.get(output_id).unwrap()
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            state: self.state.clone(),
+            output: self.output.clone(),
+        }
     }
 
-    pub fn get_visible_state(&self) -> ArrayView1<f64> {
-        self.state.slice(s![..self.visible_count])
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), Box<dyn std::error::Error>> {
+        snapshot.check_shape(self.state.len(), self.output.len())?;
+        self.state.assign(&snapshot.state);
+        self.output.assign(&snapshot.output);
+        Ok(())
     }
 
-    /// Perform some gradient descent training steps on the network,
-    /// using MSE as the loss function.
-    ///
-    /// returns the average squared error
-    pub fn train_mse_grad(
-        &mut self,
-        inputs: &[Array1<f64>],
-        targets: &[Option<Array1<f64>>],
-    ) -> f64 {
-        assert!(inputs.len() == targets.len());
-
-        // initialize a zero state vector
-        let mut state: Array1<f64> = Array1::zeros(self.size);
-
-        // keep track of the gradient of the SE w.r.t. the output weights
-        let mut grad: Array2<f64> = Array2::zeros(self.weights_res_out.dim());
-
-        let mut error: f64 = 0.0;
-        let mut target_count = 0;
-
-        let mut grad_tmp = Array2::zeros(self.weights_res_out.dim());
-
-        for (target, input) in targets.iter().zip(inputs) {
-            let output = self.forward_external(&mut state, input);
-
-            // calculate diff error / diff output
-            let Some(target) = target else {
-                continue;
-            };
-
-            target_count += 1;
-
-            let diff = output - target;
-
-            // add to the error
-            error += diff.dot(&diff);
-
-            // calculate the gradient for this timestep
-            let diff_arr = diff.into_shape((self.outputs, 1)).unwrap();
-            let state_arr = state
-                .slice(s![..self.visible_count])
-                .into_shape((self.visible_count, 1))
-                .unwrap();
-            grad_tmp.assign(&diff_arr.dot(&state_arr.t()));
-
-            grad += &grad_tmp;
-        }
-
-        // average the gradient and error
-        grad /= target_count as f64;
-
-        // apply the gradient
-        self.weights_res_out = &self.weights_res_out - self.learning_rate * grad;
-
-        error
+    pub fn get_output(&self, output_id: usize) -> f64 {
+        *self.output.get(output_id).unwrap()
     }
 
-    /// Train the reservoir using the pseudo-inverse method.
-    ///
-    /// # Arguments
-    /// - `inputs` - A slice of input arrays
-    /// - `targets` - A slice of target arrays
-    /// - `target_times` - An optional slice of times at which the targets are to be reached
-    ///
-    /// # Returns
-    /// The squared error of the training step
-    ///
-    /// # Note
-    /// - This method does not reset the state of the reservoir, so the user can decide when to do so
-    ///   themselves.
-    /// - The targets slice can be either the length of the inputs slice, or the length of the target_times Vec.
-    ///   This gives the user the flexibility to specify the target output at specific times only,
-    ///   or to provide the target output at every time step.
-    /// - The returned error is the error the model makes at the moment, thus before the training
-    ///   step
-    pub fn train_step(
-        &mut self,
-        inputs: &[Array1<f64>],
-        targets: &[Option<Array1<f64>>],
-        offset: usize,
-    ) -> f64 {
-        // if the target times are given, we only train the network at those times
-        // otherwise, we train at all times
-        let train_instants_count = targets.iter().filter(|x| x.is_some()).count();
-        let mut states: Array2<f64> = Array2::zeros((self.visible_count, train_instants_count));
-        let mut target_outputs: Array2<f64> = Array2::zeros((self.outputs, train_instants_count));
-        let mut error: f64 = 0.0;
-
-        let mut column_idx = 0;
-
-        // calculate all states
-        for (j, input) in inputs.iter().skip(offset).enumerate() {
-            self.forward(input);
-
-            // only train the specified times
-            let Some(target) = &targets[j] else {
-                continue;
-            };
-
-            // save the state at this target time to the states matrix
-            let slice = self.state.slice(s![..self.visible_count]);
-
-            // this slice will become a column in the states matrix
-            assert!(slice.len() == states.shape()[0]);
-            states
-                .column_mut(column_idx)
-                .iter_mut()
-                .zip(slice.iter())
-                .for_each(|(a, b)| *a = *b);
-
-            // save the output at the target time to the target_outputs matrix
-            self.output
-                .iter()
-                .enumerate()
-                .for_each(|(i, output)| error += (target[i] - output).powi(2));
-
-            column_idx += 1;
-        }
-
-        // get rid of none values
-        let targets = targets.iter().flatten().collect::<Vec<_>>();
-
-        // get these target outputs into a ndarray matrix
-        target_outputs
-            // iterate over columns
-            .axis_iter_mut(Axis(1))
-            // zip with target instances (each column is one moment in time)
-            .zip(targets.iter())
-            .for_each(|(mut col, target)| {
-                // assign each row in this column
-                col.iter_mut().zip(target.iter()).for_each(|(a, b)| *a = *b);
-            });
-
-        // pseudo-inverse calculation -> doesn't allow for regularization!
-        let pseudo_inv = pseudo_inverse(&states, self.regularization);
-
-        let new_weights = match pseudo_inv {
-            Ok(pinv) => target_outputs.dot(&pinv),
-            Err(_) => {
-                // SVD failed, use the regular Moore-Penrose pseudo-inverse method
-
-                let yxt = target_outputs.dot(&states.t());
-                let xxt = states.dot(&states.t());
-                let lambdas = self.regularization * Array2::eye(self.visible_count);
-                let xxt_lambda_inv = (xxt + lambdas).inv().unwrap();
-                yxt.dot(&xxt_lambda_inv)
-            }
-        };
-
-        self.weights_res_out =
-            (1.0 - self.learning_rate) * &self.weights_res_out + self.learning_rate * new_weights;
-
-        error
+    pub fn get_visible_state(&self) -> ArrayView1<f64> {
+        self.state.slice(s![..self.visible_count])
     }
 
     /// Set the sparse representation if it doesn't already exist
@@ -630,62 +846,6 @@ impl Reservoir {
         self.weights_rr_sparse = Some(result);
     }
 
-    /// Fully train the network
-    pub fn train(&mut self, args: &TrainArgs) -> Result<f64, Box<dyn std::error::Error>> {
-        let shift = args
-            .shift
-            .map(|shift| (shift as f64 / args.timestep).round() as usize);
-
-        let (inputs, targets) = load_train_data(
-            &args.data,
-            args.timestep,
-            args.width,
-            args.target_width,
-            shift,
-        )?;
-
-        // keep history of output weights to jump back to a previous better version
-        // to get rid of the weird training behaviour (which will need to be investigated further)
-        let mut weight_history: Array2<f64> = Array2::zeros(self.weights_res_out.dim());
-        let mut best_weights = self.weights_res_out.clone();
-        let mut lowest_error = std::f64::MAX;
-        let mut last_error = 0.0;
-
-        for i in 0..args.iter {
-            // save the history before any adjustments
-            weight_history.assign(&self.weights_res_out);
-
-            let error = match args.mode {
-                TrainMode::Inv => self.train_step(&inputs, &targets, (i as usize * 15) % 31),
-                TrainMode::Grad => self.train_mse_grad(&inputs, &targets),
-            };
-
-            // important...
-            self.reset_state();
-
-            if args.dont_stop_early {
-                continue;
-            }
-
-            if error < lowest_error {
-                best_weights = weight_history.clone();
-                lowest_error = error;
-            }
-
-            let diff = (last_error - error).abs();
-            if diff < 1e-4 {
-                log::info!("Stopping early at iteration {}", i);
-                break;
-            }
-
-            last_error = error;
-        }
-
-        self.set_weights_out(best_weights);
-
-        Ok(lowest_error)
-    }
-
     pub fn plot<P>(&mut self, args: &TrainArgs, output: P) -> Result<(), Box<dyn std::error::Error>>
     where
         P: Into<PathBuf>,
@@ -700,6 +860,7 @@ impl Reservoir {
             args.width,
             args.target_width,
             shift,
+            args.encoding,
         )?;
 
         self.reset_state();
@@ -727,20 +888,193 @@ impl Reservoir {
                 }
             });
         }
-        python!("plot.py", csv_path, svg_path);
+        // a failed plot should not abort a sweep, the data is in the CSV file anyway
+        if let Err(e) = plot_csv(csv_path, svg_path) {
+            log::warn!("Could not plot {}: {}", svg_path, e);
+        }
 
         Ok(())
     }
 }
 
+impl LinearReadout for Reservoir {
+    fn forward(&mut self, input: &Array1<f64>) {
+        Reservoir::forward(self, input);
+    }
+
+    fn reset_state(&mut self) {
+        Reservoir::reset_state(self);
+    }
+
+    fn readout_state(&self) -> ArrayView1<'_, f64> {
+        self.get_visible_state()
+    }
+
+    fn output(&self) -> &Array1<f64> {
+        &self.output
+    }
+
+    fn readout_weights(&self) -> &Array2<f64> {
+        &self.weights_res_out
+    }
+
+    fn readout_weights_mut(&mut self) -> &mut Array2<f64> {
+        &mut self.weights_res_out
+    }
+
+    fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    fn outputs(&self) -> usize {
+        self.outputs
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+
+    fn regularization(&self) -> f64 {
+        self.regularization
+    }
+
+    fn set_regularization(&mut self, lambda: f64) {
+        self.regularization = lambda;
+    }
+
+    fn teacher_force(&mut self, target: Option<&Array1<f64>>) {
+        match target {
+            Some(target) => self.output.assign(target),
+            None => self.output.fill(0.0),
+        }
+    }
+
+    fn save(
+        &self,
+        name: &str,
+        metadata: Option<&TrainArgs>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Reservoir::save(self, name, metadata)
+    }
+
+    fn boxed_clone(&self) -> Box<dyn LinearReadout + Send> {
+        Box::new(self.clone())
+    }
+
+    fn washout(&self) -> usize {
+        self.warm_up
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use make_csv::{csv_entry, csv_start, csv_stop};
+    use ndarray::Axis;
 
-    use crate::data::load_train_data;
+    use crate::{data::load_train_data, encoding::InputEncoding};
 
     use super::*;
 
+    #[test]
+    fn power_iteration_matches_eigenvalues() {
+        let mut nw = Reservoir::new_builder()
+            .seed(7)
+            .from_size_input_outputs(200, 1, 1, 0.1)
+            .build();
+
+        let (eig, _) = nw.weights_res_res.eig().unwrap();
+        let exact = eig.iter().map(|e| e.norm()).fold(0.0, f64::max);
+        nw.generate_sparse();
+        let estimate = estimate_spectral_radius(nw.weights_rr_sparse.as_ref().unwrap());
+
+        assert!((estimate - exact).abs() / exact < 0.05);
+    }
+
+    /// The amount of nonzero resonant weights in every column
+    fn column_connections(nw: &Reservoir) -> Vec<usize> {
+        nw.weights_res_res
+            .axis_iter(Axis(1))
+            .map(|column| column.iter().filter(|w| **w != 0.0).count())
+            .collect()
+    }
+
+    #[test]
+    fn cycle_is_a_single_ring() {
+        let nw = Reservoir::new_builder()
+            .seed(7)
+            .from_cycle(10, 1, 1)
+            .build();
+
+        assert_eq!(column_connections(&nw), vec![1; 10]);
+        assert_eq!(nw.weights_res_res[[0, 9]], 1.0);
+        assert_eq!(nw.weights_in_res.dim(), (10, 1));
+    }
+
+    #[test]
+    fn delay_line_is_nilpotent_and_not_rescaled() {
+        let mut nw = Reservoir::new_builder()
+            .seed(7)
+            .from_delay_line(10, 1, 1)
+            .build();
+
+        let mut power = nw.weights_res_res.clone();
+        for _ in 1..10 {
+            power = power.dot(&nw.weights_res_res);
+        }
+        assert!(power.iter().all(|w| *w == 0.0));
+
+        let weights = nw.weights_res_res.clone();
+        nw.scale(Some(0.9));
+        assert_eq!(nw.weights_res_res, weights);
+    }
+
+    #[test]
+    fn jump_connections_are_symmetric() {
+        let nw = Reservoir::new_builder()
+            .seed(7)
+            .from_cycle_jumps(12, 1, 1, 3)
+            .build();
+
+        for i in (0..12).step_by(3) {
+            let j = (i + 3) % 12;
+            assert_eq!(nw.weights_res_res[[i, j]], JUMP_WEIGHT);
+            assert_eq!(nw.weights_res_res[[j, i]], JUMP_WEIGHT);
+        }
+    }
+
+    #[test]
+    fn rewiring_keeps_the_edge_count() {
+        let connections = |rewire| {
+            let nw = Reservoir::new_builder()
+                .seed(7)
+                .from_small_world(50, 1, 1, 4, rewire)
+                .build();
+            column_connections(&nw).iter().sum::<usize>()
+        };
+
+        // every neuron has 4 neighbors, and every edge is in both directions
+        assert_eq!(connections(0.0), 200);
+        assert_eq!(connections(0.3), 200);
+        assert_eq!(connections(1.0), 200);
+    }
+
+    #[test]
+    fn sparse_construction_skips_dense_weights() {
+        let mut nw = Reservoir::new_builder()
+            .seed(7)
+            .from_size_input_outputs_sparse(3000, 1, 1, 0.01)
+            .build();
+        nw.scale(Some(0.9));
+
+        assert_eq!(nw.weights_res_res.len(), 0);
+        let sparse = nw.weights_rr_sparse.as_ref().unwrap();
+        assert!((sparse.nnz() as f64 - 90_000.0).abs() < 3_000.0);
+        assert!((estimate_spectral_radius(sparse) - 0.9).abs() < 0.05);
+
+        nw.forward(&Array1::ones(1));
+        assert_eq!(nw.state.len(), 3000);
+    }
+
     #[test]
     fn example_run_for_plots() {
         let data_name = "default";
@@ -752,8 +1086,15 @@ mod tests {
 
         let mut nw = Reservoir::load_from_name(model_name).unwrap();
 
-        let (inputs, _targets) =
-            load_train_data(data_name, timestep, width, target_width, None).unwrap();
+        let (inputs, _targets) = load_train_data(
+            data_name,
+            timestep,
+            width,
+            target_width,
+            None,
+            InputEncoding::Pulse,
+        )
+        .unwrap();
 
         let mut wtr = csv_start!("data/width_10.csv");
 
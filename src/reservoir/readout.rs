@@ -0,0 +1,763 @@
+/*!
+* Linear readout solvers for the reservoir.
+*
+* The readout is trained on a matrix of harvested states (one column per target instant), using
+* Tikhonov regularized (ridge) regression. The regularization parameter can be selected
+* automatically using k-fold cross-validation over a grid of candidate values.
+*
+* For online training, the readout can also be updated sample by sample using recursive least
+* squares (as used in FORCE learning).
+*
+* The [`LinearReadout`] trait implements all training methods for any model that consists of a
+* (possibly deep) state, followed by a trainable linear readout.
+*/
+
+use std::error::Error;
+
+use ndarray::{concatenate, s, Array1, Array2, ArrayView1, Axis};
+use ndarray_linalg::{Inverse, SVD};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    commands::{TrainArgs, TrainMode},
+    data::{load_extra_data, load_train_data, target_channels, Data},
+};
+
+/// Result of a k-fold cross-validation sweep over regularization values
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrossValidation {
+    /// The amount of folds that were used
+    pub folds: usize,
+    /// The selected regularization parameter (lowest mean held-out error)
+    pub lambda: f64,
+    /// All regularization parameters that were tried
+    pub lambdas: Vec<f64>,
+    /// Mean held-out error for each of the tried regularization parameters
+    pub scores: Vec<f64>,
+    /// Held-out error of each fold, for the selected regularization parameter
+    pub fold_scores: Vec<f64>,
+}
+
+/// Create `count` logarithmically spaced values between `min` and `max` (inclusive)
+pub fn lambda_grid(min: f64, max: f64, count: usize) -> Vec<f64> {
+    if count == 1 {
+        return vec![min];
+    }
+
+    let (log_min, log_max) = (min.log10(), max.log10());
+    (0..count)
+        .map(|i| 10f64.powf(log_min + (log_max - log_min) * i as f64 / (count - 1) as f64))
+        .collect()
+}
+
+/// Solve the ridge regression problem for the readout weights.
+///
+/// Calculates `W = Y X^T (X X^T + lambda I)^-1`, where `X` are the states (`neurons x instants`)
+/// and `Y` are the targets (`outputs x instants`).
+pub fn ridge_regression(
+    states: &Array2<f64>,
+    targets: &Array2<f64>,
+    lambda: f64,
+) -> Result<Array2<f64>, String> {
+    let yxt = targets.dot(&states.t());
+    let xxt = states.dot(&states.t());
+    let lambdas = lambda * Array2::eye(states.nrows());
+
+    let Ok(xxt_lambda_inv) = (xxt + lambdas).inv() else {
+        return Err("Ridge regression failed @ matrix inversion".to_string());
+    };
+
+    Ok(yxt.dot(&xxt_lambda_inv))
+}
+
+pub fn pseudo_inverse(matrix: &Array2<f64>, regularization: f64) -> Result<Array2<f64>, String> {
+    let start = std::time::Instant::now();
+    let Ok(svd_result) = matrix.svd(true, true) else {
+        return Err("SVD failed @ calculation".to_string());
+    };
+
+    let (Some(u), sigma, Some(vt)) = svd_result else {
+        return Err("SVD failed @ unpacking into U, Sigma, V".to_string());
+    };
+
+    let mut s = Array2::zeros((matrix.shape()[0], matrix.shape()[1]));
+
+    for (i, val) in sigma.iter().enumerate() {
+        // if the value is very small, we set it to zero (which may be weird?)
+        if *val < 1e-9 {
+            s[[i, i]] = 0.0;
+        } else {
+            s[[i, i]] = 1.0 / (val + regularization);
+        }
+    }
+
+    let pseudo_inv = vt.t().dot(&s.t()).dot(&u.t());
+    log::debug!("Pseudo-inverse calculation: {:?}", start.elapsed());
+
+    Ok(pseudo_inv)
+}
+
+/// The mean squared error of the readout `weights` on the given states and targets
+pub fn mse(weights: &Array2<f64>, states: &Array2<f64>, targets: &Array2<f64>) -> f64 {
+    let diff = weights.dot(states) - targets;
+    diff.mapv(|x| x * x).sum() / targets.len().max(1) as f64
+}
+
+/// Select the regularization parameter using k-fold cross-validation.
+///
+/// The columns of the harvested `states` and `targets` are split into `folds` contiguous blocks,
+/// which keeps the temporal structure of the data intact. Each block is held out once, while the
+/// readout is fitted on the remaining blocks.
+pub fn cross_validate(
+    states: &Array2<f64>,
+    targets: &Array2<f64>,
+    folds: usize,
+    lambdas: &[f64],
+) -> Result<CrossValidation, String> {
+    let instants = states.ncols();
+
+    if folds < 2 || folds > instants {
+        return Err(format!(
+            "Cross-validation needs between 2 and {} folds, got {}",
+            instants, folds
+        ));
+    }
+    if lambdas.is_empty() {
+        return Err("Cross-validation needs at least one regularization value".to_string());
+    }
+
+    let fold_len = instants / folds;
+    let bounds: Vec<(usize, usize)> = (0..folds)
+        .map(|f| {
+            let end = if f == folds - 1 {
+                instants
+            } else {
+                (f + 1) * fold_len
+            };
+            (f * fold_len, end)
+        })
+        .collect();
+
+    let mut all_fold_scores = Vec::with_capacity(lambdas.len());
+
+    for &lambda in lambdas {
+        let mut fold_scores = Vec::with_capacity(folds);
+
+        for &(start, end) in &bounds {
+            let train_states = concatenate![
+                Axis(1),
+                states.slice(s![.., ..start]),
+                states.slice(s![.., end..])
+            ];
+            let train_targets = concatenate![
+                Axis(1),
+                targets.slice(s![.., ..start]),
+                targets.slice(s![.., end..])
+            ];
+
+            let weights = ridge_regression(&train_states, &train_targets, lambda)?;
+
+            let test_states = states.slice(s![.., start..end]).to_owned();
+            let test_targets = targets.slice(s![.., start..end]).to_owned();
+            fold_scores.push(mse(&weights, &test_states, &test_targets));
+        }
+
+        log::debug!("Cross-validation lambda {:e}: {:?}", lambda, fold_scores);
+        all_fold_scores.push(fold_scores);
+    }
+
+    let scores: Vec<f64> = all_fold_scores
+        .iter()
+        .map(|f| f.iter().sum::<f64>() / f.len() as f64)
+        .collect();
+
+    let best = scores
+        .iter()
+        .enumerate()
+        .min_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(i, _)| i)
+        .unwrap();
+
+    Ok(CrossValidation {
+        folds,
+        lambda: lambdas[best],
+        lambdas: lambdas.to_vec(),
+        scores,
+        fold_scores: all_fold_scores.swap_remove(best),
+    })
+}
+
+/// Check that a forgetting factor of recursive least squares is in `(0, 1]`.
+///
+/// Outside of that range the inverse correlation matrix blows up (or is divided by zero), which
+/// silently fills the readout weights with NaN.
+pub fn check_forgetting(forgetting: f64) -> Result<(), String> {
+    if forgetting > 0.0 && forgetting <= 1.0 {
+        Ok(())
+    } else {
+        Err(format!(
+            "The forgetting factor should be in (0, 1], got {}",
+            forgetting
+        ))
+    }
+}
+
+/// Recursive least squares (RLS) state for online training of a linear readout.
+pub struct RecursiveLeastSquares {
+    /// estimate of the inverse correlation matrix of the states
+    p: Array2<f64>,
+    /// forgetting factor (1.0 means no forgetting)
+    forgetting: f64,
+}
+
+impl RecursiveLeastSquares {
+    /// Create a new RLS state for `size` readout inputs.
+    ///
+    /// The inverse correlation matrix starts as `I / regularization`, which makes RLS equivalent
+    /// to ridge regression with that regularization (without forgetting).
+    pub fn new(size: usize, regularization: f64, forgetting: f64) -> Self {
+        Self {
+            p: Array2::eye(size) / regularization.max(f64::EPSILON),
+            forgetting,
+        }
+    }
+
+    /// Update the readout `weights` for one sample.
+    ///
+    /// # Arguments
+    /// - `weights` - The readout weights (`outputs x states`), updated in place
+    /// - `state` - The state that produced the current `output`
+    /// - `output` - The readout's output for this state (before the update)
+    /// - `target` - The desired output for this state
+    pub fn update(
+        &mut self,
+        weights: &mut Array2<f64>,
+        state: ArrayView1<f64>,
+        output: &Array1<f64>,
+        target: &Array1<f64>,
+    ) {
+        let p_state = self.p.dot(&state);
+        let gain = &p_state / (self.forgetting + state.dot(&p_state));
+        let error = target - output;
+
+        // W += e k^T
+        weights.zip_mut_with(
+            &error
+                .view()
+                .insert_axis(Axis(1))
+                .dot(&gain.view().insert_axis(Axis(0))),
+            |w, dw| *w += dw,
+        );
+
+        // P = (P - k (P x)^T) / forgetting, using the symmetry of P
+        let correction = gain
+            .view()
+            .insert_axis(Axis(1))
+            .dot(&p_state.view().insert_axis(Axis(0)));
+        self.p = (&self.p - &correction) / self.forgetting;
+    }
+}
+
+/// An independent sequence of inputs, with the (optional) target at every timestep
+pub type Sequence<'a> = (&'a [Array1<f64>], &'a [Option<Array1<f64>>]);
+
+/// A model with an internal state, and a linear readout from that state to the outputs.
+///
+/// Only the readout weights are trained, so all training methods are shared by the
+/// implementors of this trait.
+pub trait LinearReadout {
+    /// Apply one timestep: update the state using the input, and calculate the output
+    fn forward(&mut self, input: &Array1<f64>);
+
+    /// Reset the state and output to zero
+    fn reset_state(&mut self);
+
+    /// The state that the readout is applied to
+    fn readout_state(&self) -> ArrayView1<'_, f64>;
+
+    /// The output of the last timestep
+    fn output(&self) -> &Array1<f64>;
+
+    /// The readout weights (`outputs x readout state`)
+    fn readout_weights(&self) -> &Array2<f64>;
+
+    fn readout_weights_mut(&mut self) -> &mut Array2<f64>;
+
+    /// Number of inputs
+    fn inputs(&self) -> usize;
+
+    /// Number of outputs
+    fn outputs(&self) -> usize;
+
+    fn learning_rate(&self) -> f64;
+
+    fn regularization(&self) -> f64;
+
+    fn set_regularization(&mut self, lambda: f64);
+
+    /// Save the model in the models directory, optionally with the arguments it was trained with
+    fn save(&self, name: &str, metadata: Option<&TrainArgs>) -> Result<(), Box<dyn Error>>;
+
+    /// A copy of the model, used to harvest sequences in parallel
+    fn boxed_clone(&self) -> Box<dyn LinearReadout + Send>;
+
+    /// The amount of timesteps at the start of a sequence of which the states are discarded
+    fn washout(&self) -> usize {
+        0
+    }
+
+    /// Replace the output that is fed back into the model at the next timestep by the target
+    /// (teacher forcing). Timesteps without a target feed back zero.
+    ///
+    /// Only models with output feedback need to implement this.
+    fn teacher_force(&mut self, _target: Option<&Array1<f64>>) {}
+
+    fn set_weights_out(&mut self, weights: Array2<f64>) {
+        *self.readout_weights_mut() = weights;
+    }
+
+    /// Perform some gradient descent training steps on the network,
+    /// using MSE as the loss function.
+    ///
+    /// returns the average squared error
+    ///
+    /// # Note
+    /// The state of the model is reset before each sequence.
+    fn train_mse_grad(&mut self, sequences: &[Sequence]) -> f64 {
+        // keep track of the gradient of the SE w.r.t. the output weights
+        let mut grad: Array2<f64> = Array2::zeros(self.readout_weights().dim());
+
+        let mut error: f64 = 0.0;
+        let mut target_count = 0;
+
+        let mut grad_tmp = Array2::zeros(self.readout_weights().dim());
+
+        for (inputs, targets) in sequences {
+            assert!(inputs.len() == targets.len());
+
+            // start from a zero state
+            self.reset_state();
+
+            for (j, (target, input)) in targets.iter().zip(inputs.iter()).enumerate() {
+                self.forward(input);
+
+                let output = self.output().clone();
+                self.teacher_force(target.as_ref());
+
+                // calculate diff error / diff output, after the washout
+                let Some(target) = target.as_ref().filter(|_| j >= self.washout()) else {
+                    continue;
+                };
+
+                target_count += 1;
+
+                let diff = output - target;
+
+                // add to the error
+                error += diff.dot(&diff);
+
+                // calculate the gradient for this timestep
+                let state = self.readout_state();
+                let diff_arr = diff.into_shape((self.outputs(), 1)).unwrap();
+                let state_arr = state.into_shape((state.len(), 1)).unwrap();
+                grad_tmp.assign(&diff_arr.dot(&state_arr.t()));
+
+                grad += &grad_tmp;
+            }
+        }
+
+        // average the gradient and error
+        grad /= target_count.max(1) as f64;
+
+        // apply the gradient
+        let new_weights = self.readout_weights() - self.learning_rate() * grad;
+        self.set_weights_out(new_weights);
+
+        error
+    }
+
+    /// Run the model over the inputs, and collect the readout states at the target instants.
+    ///
+    /// The states of the first [`LinearReadout::washout`] timesteps are discarded, as these still
+    /// depend on the initial state of the model.
+    ///
+    /// # Returns
+    /// A tuple of the states matrix (`readout state x target instants`), the matching target
+    /// matrix (`outputs x target instants`) and the squared error of the current readout at those
+    /// instants.
+    ///
+    /// # Note
+    /// This method does not reset the state of the model.
+    fn harvest_states(
+        &mut self,
+        inputs: &[Array1<f64>],
+        targets: &[Option<Array1<f64>>],
+    ) -> (Array2<f64>, Array2<f64>, f64) {
+        let washout = self.washout();
+
+        // if the target times are given, we only train the network at those times
+        // otherwise, we train at all times
+        let train_instants_count = targets.iter().skip(washout).filter(|x| x.is_some()).count();
+        let mut states: Array2<f64> =
+            Array2::zeros((self.readout_state().len(), train_instants_count));
+        let mut target_outputs: Array2<f64> = Array2::zeros((self.outputs(), train_instants_count));
+        let mut error: f64 = 0.0;
+
+        let mut column_idx = 0;
+
+        // calculate all states
+        for (j, (input, target)) in inputs.iter().zip(targets).enumerate() {
+            self.forward(input);
+
+            // only train the specified times, after the washout
+            let Some(target) = target.as_ref().filter(|_| j >= washout) else {
+                self.teacher_force(target.as_ref());
+                continue;
+            };
+
+            // save the state at this target time to the states matrix
+            let slice = self.readout_state();
+
+            // this slice will become a column in the states matrix
+            assert!(slice.len() == states.shape()[0]);
+            states.column_mut(column_idx).assign(&slice);
+
+            // save the target and the error at the target time
+            target_outputs.column_mut(column_idx).assign(target);
+            self.output()
+                .iter()
+                .enumerate()
+                .for_each(|(i, output)| error += (target[i] - output).powi(2));
+
+            self.teacher_force(Some(target));
+
+            column_idx += 1;
+        }
+
+        (states, target_outputs, error)
+    }
+
+    /// Harvest the states of several independent sequences, and concatenate them.
+    ///
+    /// Every sequence is harvested from a reset copy of the model, in parallel.
+    ///
+    /// # Returns
+    /// The concatenated states and target matrices, and the summed squared error (see
+    /// [`LinearReadout::harvest_states`]).
+    fn harvest_sequences(&mut self, sequences: &[Sequence]) -> (Array2<f64>, Array2<f64>, f64) {
+        if let [(inputs, targets)] = sequences {
+            return self.harvest_states(inputs, targets);
+        }
+
+        let mut models: Vec<_> = sequences.iter().map(|_| self.boxed_clone()).collect();
+        let harvested: Vec<_> = models
+            .par_iter_mut()
+            .zip(sequences.par_iter())
+            .map(|(model, (inputs, targets))| {
+                model.reset_state();
+                model.harvest_states(inputs, targets)
+            })
+            .collect();
+
+        let states: Vec<_> = harvested
+            .iter()
+            .map(|(states, _, _)| states.view())
+            .collect();
+        let targets: Vec<_> = harvested
+            .iter()
+            .map(|(_, targets, _)| targets.view())
+            .collect();
+        let error = harvested.iter().map(|(_, _, error)| error).sum();
+
+        (
+            concatenate(Axis(1), &states).unwrap(),
+            concatenate(Axis(1), &targets).unwrap(),
+            error,
+        )
+    }
+
+    /// Train the model using the pseudo-inverse method.
+    ///
+    /// # Arguments
+    /// - `sequences` - Independent sequences of inputs and (optional) targets
+    ///
+    /// # Returns
+    /// The squared error of the training step
+    ///
+    /// # Note
+    /// - Every sequence starts from a reset state.
+    /// - The targets can be given at every timestep, or only at specific times (`None` elsewhere).
+    /// - The returned error is the error the model makes at the moment, thus before the training
+    ///   step
+    fn train_step(&mut self, sequences: &[Sequence]) -> f64 {
+        let (states, target_outputs, error) = self.harvest_sequences(sequences);
+
+        let pseudo_inv = pseudo_inverse(&states, self.regularization());
+
+        let new_weights = match pseudo_inv {
+            Ok(pinv) => target_outputs.dot(&pinv),
+            Err(_) => {
+                // SVD failed, use the regular Moore-Penrose pseudo-inverse method
+
+                let yxt = target_outputs.dot(&states.t());
+                let xxt = states.dot(&states.t());
+                let lambdas = self.regularization() * Array2::eye(states.nrows());
+                let xxt_lambda_inv = (xxt + lambdas).inv().unwrap();
+                yxt.dot(&xxt_lambda_inv)
+            }
+        };
+
+        let new_weights = (1.0 - self.learning_rate()) * self.readout_weights()
+            + self.learning_rate() * new_weights;
+        self.set_weights_out(new_weights);
+
+        error
+    }
+
+    /// Train the readout using ridge (Tikhonov regularized) regression.
+    ///
+    /// The readout is replaced by the closed-form solution over all sequences, using the model's
+    /// regularization parameter.
+    ///
+    /// # Returns
+    /// The squared error of the readout before the training step
+    fn train_ridge(&mut self, sequences: &[Sequence]) -> Result<f64, String> {
+        let (states, target_outputs, error) = self.harvest_sequences(sequences);
+
+        let new_weights = ridge_regression(&states, &target_outputs, self.regularization())?;
+        self.set_weights_out(new_weights);
+
+        Ok(error)
+    }
+
+    /// Create a recursive least squares state matching this model's readout.
+    ///
+    /// The model's regularization parameter is used to initialize the inverse correlation
+    /// matrix.
+    fn new_rls(&self, forgetting: f64) -> RecursiveLeastSquares {
+        RecursiveLeastSquares::new(
+            self.readout_state().len(),
+            self.regularization(),
+            forgetting,
+        )
+    }
+
+    /// Update the readout with one recursive least squares step, using the current state and
+    /// output of the model.
+    ///
+    /// This can be called after every [`LinearReadout::forward`], both during offline training
+    /// and in a live session.
+    fn rls_step(&mut self, rls: &mut RecursiveLeastSquares, target: &Array1<f64>) {
+        let state = self.readout_state().to_owned();
+        let output = self.output().clone();
+        rls.update(self.readout_weights_mut(), state.view(), &output, target);
+    }
+
+    /// Train the readout sample by sample using recursive least squares.
+    ///
+    /// # Returns
+    /// The squared error of the readout during this pass, measured before each update
+    ///
+    /// # Note
+    /// The state of the model is reset before each sequence.
+    fn train_rls(&mut self, sequences: &[Sequence], rls: &mut RecursiveLeastSquares) -> f64 {
+        let mut error = 0.0;
+
+        for (inputs, targets) in sequences {
+            self.reset_state();
+
+            for (j, (input, target)) in inputs.iter().zip(targets.iter()).enumerate() {
+                self.forward(input);
+
+                let Some(target) = target.as_ref().filter(|_| j >= self.washout()) else {
+                    self.teacher_force(target.as_ref());
+                    continue;
+                };
+
+                let diff = target - self.output();
+                error += diff.dot(&diff);
+
+                self.rls_step(rls, target);
+                self.teacher_force(Some(target));
+            }
+        }
+
+        error
+    }
+
+    /// Select the regularization parameter using k-fold cross-validation on the harvested states.
+    ///
+    /// The selected value is used as the model's regularization parameter from now on.
+    /// The state of the model is reset before and after harvesting.
+    fn cross_validate(
+        &mut self,
+        sequences: &[Sequence],
+        folds: usize,
+        lambdas: &[f64],
+    ) -> Result<CrossValidation, String> {
+        self.reset_state();
+        let (states, target_outputs, _) = self.harvest_sequences(sequences);
+        self.reset_state();
+
+        let cv = cross_validate(&states, &target_outputs, folds, lambdas)?;
+        self.set_regularization(cv.lambda);
+
+        Ok(cv)
+    }
+
+    /// Fully train the network on the train split of its data
+    ///
+    /// # Returns
+    /// The lowest training error, and the loaded data (e.g. to evaluate the test split)
+    fn train(&mut self, args: &TrainArgs) -> Result<(f64, Data), Box<dyn std::error::Error>> {
+        let shift = args
+            .shift
+            .map(|shift| (shift as f64 / args.timestep).round() as usize);
+
+        let (inputs, targets) = load_train_data(
+            &args.data,
+            args.timestep,
+            args.width,
+            args.target_width,
+            shift,
+            args.encoding,
+        )?;
+
+        let extra_data = load_extra_data(args)?;
+
+        let train_len = (inputs.len() as f64 * args.split) as usize;
+        let mut sequences: Vec<Sequence> = vec![(&inputs[..train_len], &targets[..train_len])];
+        sequences.extend(extra_data.iter().map(|(i, t)| (i.as_slice(), t.as_slice())));
+
+        if target_channels(&targets) != self.outputs() {
+            return Err(format!(
+                "Data `{}` has {} target channels, but the model has {} outputs",
+                args.data,
+                target_channels(&targets),
+                self.outputs()
+            )
+            .into());
+        }
+
+        // keep history of output weights to jump back to a previous better version
+        // to get rid of the weird training behaviour (which will need to be investigated further)
+        let mut weight_history: Array2<f64> = Array2::zeros(self.readout_weights().dim());
+        let mut best_weights = self.readout_weights().clone();
+        let mut lowest_error = std::f64::MAX;
+        let mut last_error = 0.0;
+
+        // the folds are scored with ridge regression, so the readout is trained with it too
+        let mode = match args.folds {
+            Some(folds) => {
+                let cv = self.cross_validate(&sequences, folds, &args.cv_lambdas())?;
+                log::info!(
+                    "Selected regularization {:e} using cross-validation",
+                    cv.lambda
+                );
+                TrainMode::Ridge
+            }
+            None => args.mode.clone(),
+        };
+
+        let mut rls = self.new_rls(args.forgetting);
+
+        for i in 0..args.iter {
+            // save the history before any adjustments
+            weight_history.assign(self.readout_weights());
+
+            let error = match mode {
+                TrainMode::Inv => self.train_step(&sequences),
+                TrainMode::Grad => self.train_mse_grad(&sequences),
+                TrainMode::Ridge => self.train_ridge(&sequences)?,
+                TrainMode::Rls => self.train_rls(&sequences, &mut rls),
+            };
+
+            // important...
+            self.reset_state();
+
+            if args.dont_stop_early {
+                continue;
+            }
+
+            if error < lowest_error {
+                best_weights = weight_history.clone();
+                lowest_error = error;
+            }
+
+            let diff = (last_error - error).abs();
+            if diff < 1e-4 {
+                log::info!("Stopping early at iteration {}", i);
+                break;
+            }
+
+            last_error = error;
+        }
+
+        self.set_weights_out(best_weights);
+
+        Ok((lowest_error, (inputs, targets)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::array;
+    use ndarray_rand::{rand_distr::StandardNormal, RandomExt};
+
+    use super::*;
+
+    #[test]
+    fn ridge_recovers_linear_map() {
+        let states: Array2<f64> = Array2::random((3, 200), StandardNormal);
+        let weights = array![[0.5, -1.0, 2.0]];
+        let targets = weights.dot(&states);
+
+        let solved = ridge_regression(&states, &targets, 1e-9).unwrap();
+
+        solved
+            .iter()
+            .zip(weights.iter())
+            .for_each(|(a, b)| assert!((a - b).abs() < 1e-4));
+    }
+
+    #[test]
+    fn rls_converges_to_linear_map() {
+        let states: Array2<f64> = Array2::random((3, 500), StandardNormal);
+        let weights = array![[0.5, -1.0, 2.0]];
+        let targets = weights.dot(&states);
+
+        let mut solved = Array2::zeros((1, 3));
+        let mut rls = RecursiveLeastSquares::new(3, 1e-6, 1.0);
+        for (state, target) in states.columns().into_iter().zip(targets.columns()) {
+            let output = solved.dot(&state);
+            rls.update(&mut solved, state, &output, &target.to_owned());
+        }
+
+        solved
+            .iter()
+            .zip(weights.iter())
+            .for_each(|(a, b)| assert!((a - b).abs() < 1e-3));
+    }
+
+    #[test]
+    fn forgetting_factor_is_validated() {
+        assert!(check_forgetting(1.0).is_ok());
+        assert!(check_forgetting(0.99).is_ok());
+        assert!(check_forgetting(0.0).is_err());
+        assert!(check_forgetting(-0.5).is_err());
+        assert!(check_forgetting(1.01).is_err());
+        assert!(check_forgetting(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn cross_validation_prefers_small_lambda_without_noise() {
+        let states: Array2<f64> = Array2::random((4, 100), StandardNormal);
+        let targets = array![[1.0, 0.0, -1.0, 0.5]].dot(&states);
+
+        let cv = cross_validate(&states, &targets, 5, &lambda_grid(1e-6, 1e2, 5)).unwrap();
+
+        assert_eq!(cv.lambda, cv.lambdas[0]);
+        assert_eq!(cv.fold_scores.len(), 5);
+    }
+}
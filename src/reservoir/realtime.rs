@@ -0,0 +1,258 @@
+/*!
+* Allocation-free inference path for a trained reservoir.
+*
+* [`Reservoir::forward`] allocates several arrays per timestep, which is fine while training, but
+* not when running live with a timestep of a few ms. [`RealtimeReservoir`] is an inference-only
+* copy of a reservoir, which preallocates all its buffers, and can use single precision (`f32`).
+*/
+
+use std::error::Error;
+
+use ndarray::{linalg::general_mat_vec_mul, s, Array1, Array2, ArrayView1, LinalgScalar};
+use rand_distr::num_traits::Float;
+
+use super::Reservoir;
+use crate::{
+    activation::Activation,
+    model::{ModelMetadata, RhythmModel, Snapshot},
+};
+
+/// An inference-only reservoir, which doesn't allocate while stepping.
+pub struct RealtimeReservoir<F> {
+    /// resonant weights (reservoir -> reservoir), in CSR format
+    indptr: Vec<usize>,
+    indices: Vec<usize>,
+    data: Vec<F>,
+    /// input weights (input -> reservoir)
+    weights_in_res: Array2<F>,
+    /// output feedback weights (output -> reservoir)
+    weights_out_res: Array2<F>,
+    /// output weights (reservoir -> output)
+    weights_res_out: Array2<F>,
+    /// bias for the reservoir
+    bias_res: Array1<F>,
+    activation: Activation,
+    leak_rate: F,
+    /// The number of neurons that are visible to the output
+    visible_count: usize,
+    /// internal state of the reservoir
+    state: Array1<F>,
+    /// scratch buffer for the state update
+    new_state: Array1<F>,
+    /// output layer of the reservoir
+    output: Array1<F>,
+    /// scratch buffer for inputs in the model's precision
+    input: Array1<F>,
+    /// the output in double precision, for [`RhythmModel::read_outputs`]
+    output_f64: Array1<f64>,
+}
+
+fn convert<F: Float>(x: f64) -> F {
+    F::from(x).unwrap()
+}
+
+impl<F> RealtimeReservoir<F>
+where
+    F: Float + LinalgScalar,
+{
+    /// Create the inference copy of a (trained) reservoir
+    pub fn new(nw: &Reservoir) -> Self {
+        let sparse = match &nw.weights_rr_sparse {
+            Some(sparse) => sparse.to_csr(),
+            None => sprs::CsMat::csr_from_dense(nw.weights_res_res.view(), 0.0),
+        };
+        let (indptr, indices, data) = sparse.into_raw_storage();
+
+        RealtimeReservoir {
+            indptr,
+            indices,
+            data: data.into_iter().map(convert).collect(),
+            weights_in_res: nw.weights_in_res.mapv(convert),
+            weights_out_res: nw.weights_out_res.mapv(convert),
+            weights_res_out: nw.weights_res_out.mapv(convert),
+            bias_res: nw.bias_res.mapv(convert),
+            activation: nw.activation.clone(),
+            leak_rate: convert(nw.leak_rate),
+            visible_count: nw.visible_count,
+            state: nw.state.mapv(convert),
+            new_state: Array1::zeros(nw.state.len()),
+            output: nw.output.mapv(convert),
+            input: Array1::zeros(nw.inputs),
+            output_f64: nw.output.clone(),
+        }
+    }
+
+    /// Apply one timestep, without allocating
+    pub fn forward(&mut self, input: ArrayView1<F>) {
+        self.input.assign(&input);
+        self.update();
+    }
+
+    /// Update the state and output using the input buffer
+    fn update(&mut self) {
+        // 1: Reservoir -> Reservoir
+        for (row, new) in self.new_state.iter_mut().enumerate() {
+            let mut acc = F::zero();
+            for k in self.indptr[row]..self.indptr[row + 1] {
+                acc = acc + self.data[k] * self.state[self.indices[k]];
+            }
+            *new = acc;
+        }
+
+        // input and output feedback
+        general_mat_vec_mul(
+            F::one(),
+            &self.weights_in_res,
+            &self.input,
+            F::one(),
+            &mut self.new_state,
+        );
+        general_mat_vec_mul(
+            F::one(),
+            &self.weights_out_res,
+            &self.output,
+            F::one(),
+            &mut self.new_state,
+        );
+
+        let leak_rate = self.leak_rate;
+        let activation = &self.activation;
+        self.state
+            .iter_mut()
+            .zip(self.new_state.iter())
+            .zip(self.bias_res.iter())
+            .for_each(|((x, new), bias)| {
+                *x = (F::one() - leak_rate) * *x + leak_rate * activation.apply_float(*new + *bias);
+            });
+
+        // 2: Reservoir -> Output
+        general_mat_vec_mul(
+            F::one(),
+            &self.weights_res_out,
+            &self.state.slice(s![..self.visible_count]),
+            F::zero(),
+            &mut self.output,
+        );
+    }
+
+    pub fn output(&self) -> ArrayView1<'_, F> {
+        self.output.view()
+    }
+
+    pub fn reset_state(&mut self) {
+        self.state.fill(F::zero());
+        self.output.fill(F::zero());
+        self.output_f64.fill(0.0);
+    }
+}
+
+impl<F> RhythmModel for RealtimeReservoir<F>
+where
+    F: Float + LinalgScalar,
+{
+    fn step(&mut self, input: &Array1<f64>) {
+        self.input.zip_mut_with(input, |x, i| *x = convert(*i));
+        self.update();
+
+        self.output_f64
+            .zip_mut_with(&self.output, |x, o| *x = o.to_f64().unwrap());
+    }
+
+    fn read_outputs(&self) -> ArrayView1<'_, f64> {
+        self.output_f64.view()
+    }
+
+    fn reset(&mut self) {
+        self.reset_state();
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            state: self.state.mapv(|x| x.to_f64().unwrap()),
+            output: self.output_f64.clone(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: &Snapshot) -> Result<(), Box<dyn Error>> {
+        snapshot.check_shape(self.state.len(), self.output.len())?;
+        self.state
+            .zip_mut_with(&snapshot.state, |x, s| *x = convert(*s));
+        self.output
+            .zip_mut_with(&snapshot.output, |x, o| *x = convert(*o));
+        self.output_f64.assign(&snapshot.output);
+        Ok(())
+    }
+
+    fn metadata(&self) -> ModelMetadata {
+        ModelMetadata {
+            kind: match std::mem::size_of::<F>() {
+                4 => "ESN (real-time, f32)",
+                _ => "ESN (real-time)",
+            },
+            inputs: self.input.len(),
+            outputs: self.output.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_reservoir_forward() {
+        let mut nw = Reservoir::new_builder()
+            .seed(3)
+            .from_size_input_outputs(100, 1, 2, 0.2)
+            .leak_rate(0.3)
+            .build();
+        nw.scale(Some(0.9));
+
+        let mut realtime: RealtimeReservoir<f64> = RealtimeReservoir::new(&nw);
+        let mut realtime_f32: RealtimeReservoir<f32> = RealtimeReservoir::new(&nw);
+
+        for t in 0..50 {
+            let input = Array1::from_elem(1, (t % 7) as f64 / 7.0);
+            nw.forward(&input);
+            realtime.step(&input);
+            realtime_f32.step(&input);
+
+            for o in 0..2 {
+                assert!((nw.output[o] - realtime.read_outputs()[o]).abs() < 1e-9);
+                assert!((nw.output[o] - realtime_f32.read_outputs()[o]).abs() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn restoring_a_snapshot_replays_the_same_outputs() {
+        let nw = Reservoir::new_builder()
+            .seed(4)
+            .from_size_input_outputs(50, 1, 1, 0.2)
+            .build();
+        let mut realtime: RealtimeReservoir<f64> = RealtimeReservoir::new(&nw);
+        let input = |t: usize| Array1::from_elem(1, (t % 5) as f64 / 5.0);
+
+        (0..20).for_each(|t| realtime.step(&input(t)));
+        let snapshot = realtime.snapshot();
+        let outputs: Vec<f64> = (20..40)
+            .map(|t| {
+                realtime.step(&input(t));
+                realtime.read_outputs()[0]
+            })
+            .collect();
+
+        realtime.reset();
+        realtime.restore(&snapshot).unwrap();
+        for (t, output) in (20..40).zip(outputs) {
+            realtime.step(&input(t));
+            assert_eq!(realtime.read_outputs()[0], output);
+        }
+
+        let other = Snapshot {
+            state: Array1::zeros(10),
+            output: Array1::zeros(1),
+        };
+        assert!(realtime.restore(&other).is_err());
+    }
+}
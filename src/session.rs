@@ -0,0 +1,180 @@
+/*!
+* Recorded sessions: everything that was published on the ZMQ sockets while playing with the
+* system, with the time it was received.
+*
+* A session is stored as JSON lines (one [`SessionEvent`] per line), so that a recording that is
+* interrupted is still readable up to the last event. A session can be converted into train data
+* (see `generate-data from-session`), with the MIDI input as inputs and the played output notes as
+* targets.
+*/
+
+use std::{
+    collections::VecDeque,
+    error::Error,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    commands::FromSessionArgs,
+    data::sessions_dir,
+    errors::NeuronError,
+    midifile::{onset_series, Streams},
+};
+
+/// A message that was received during a session
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SessionMessage {
+    /// MIDI notes of the user input, and their velocity (0 to 1)
+    Input { notes: Vec<u8>, velocity: f64 },
+    /// A note at the output
+    Output,
+    /// A tempo estimate of the metronome [Hz]
+    Tempo(f64),
+    /// The output of the running model
+    Feel(f64),
+}
+
+/// A message and the time it was received
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SessionEvent {
+    /// time since the start of the recording [ms]
+    pub time: f64,
+    pub message: SessionMessage,
+}
+
+/// The path of the session file with the given name
+pub fn session_path(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let mut path = sessions_dir()?;
+    path.push(format!("{name}.jsonl"));
+    Ok(path)
+}
+
+/// Read all events of a recorded session
+pub fn read_session(name: &str) -> Result<Vec<SessionEvent>, Box<dyn Error>> {
+    let path = session_path(name)?;
+    if !path.exists() {
+        return Err(NeuronError::FileNotFound(path.display().to_string()).into());
+    }
+
+    let reader = BufReader::new(std::fs::File::open(&path)?);
+    let mut events = vec![];
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = serde_json::from_str(&line)
+            .map_err(|e| format!("Line {} of session `{}` is invalid: {}", i + 1, name, e))?;
+        events.push(event);
+    }
+
+    Ok(events)
+}
+
+/// The grid of `resolution` subdivisions per beat between the first and the last event, following
+/// the recorded tempo. Before the first tempo estimate, `bpm` is used.
+fn tempo_grid(events: &[SessionEvent], resolution: u32, bpm: f64) -> Vec<f64> {
+    let (Some(first), Some(last)) = (events.first(), events.last()) else {
+        return vec![];
+    };
+
+    let mut grid = vec![];
+    let mut beat_frequency = bpm / 60.0;
+    let mut tempos = events.iter().filter_map(|e| match e.message {
+        SessionMessage::Tempo(hz) if hz > 0.0 => Some((e.time, hz)),
+        _ => None,
+    });
+    let mut next_tempo = tempos.next();
+
+    let mut time = first.time;
+    while time <= last.time {
+        while let Some((_, hz)) = next_tempo.filter(|(t, _)| *t <= time) {
+            beat_frequency = hz;
+            next_tempo = tempos.next();
+        }
+
+        grid.push(time);
+        time += 1000.0 / beat_frequency / resolution.max(1) as f64;
+    }
+
+    grid
+}
+
+/// The input hits (time and velocity) and the output notes as target of a session
+pub fn session_to_streams(
+    events: &[SessionEvent],
+    args: &FromSessionArgs,
+    bpm: f64,
+) -> Result<Streams, Box<dyn Error>> {
+    let inputs: VecDeque<(f64, f64)> = events
+        .iter()
+        .filter_map(|e| match &e.message {
+            SessionMessage::Input { notes, velocity }
+                if args.input_notes.is_empty()
+                    || notes.iter().any(|n| args.input_notes.contains(n)) =>
+            {
+                Some((e.time, *velocity))
+            }
+            _ => None,
+        })
+        .collect();
+    let onsets: Vec<f64> = events
+        .iter()
+        .filter(|e| e.message == SessionMessage::Output)
+        .map(|e| e.time)
+        .collect();
+
+    if inputs.is_empty() || onsets.is_empty() {
+        return Err(format!(
+            "The session has {} input and {} output notes, both are needed",
+            inputs.len(),
+            onsets.len()
+        )
+        .into());
+    }
+
+    let grid = tempo_grid(events, args.resolution, bpm);
+    Ok((inputs, vec![onset_series(&onsets, &grid)]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(time: f64, message: SessionMessage) -> SessionEvent {
+        SessionEvent { time, message }
+    }
+
+    #[test]
+    fn sessions_become_streams() {
+        let input = || SessionMessage::Input {
+            notes: vec![36],
+            velocity: 0.5,
+        };
+        let events = vec![
+            event(0.0, input()),
+            event(10.0, SessionMessage::Feel(0.2)),
+            event(490.0, SessionMessage::Output),
+            // from here on a beat takes 500 ms
+            event(600.0, SessionMessage::Tempo(2.0)),
+            event(1000.0, input()),
+            event(1600.0, input()),
+        ];
+        let args = FromSessionArgs {
+            resolution: 1,
+            ..Default::default()
+        };
+
+        let (inputs, targets) = session_to_streams(&events, &args, 60.0).unwrap();
+
+        assert_eq!(inputs, vec![(0.0, 0.5), (1000.0, 0.5), (1600.0, 0.5)]);
+        // the grid point at 0 is too close to the output note
+        assert_eq!(
+            targets,
+            vec![vec![(490.0, true), (1000.0, false), (1500.0, false)]]
+        );
+    }
+}